@@ -0,0 +1,89 @@
+/// Character sets that can be designated into G0–G3.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Charset {
+    #[default]
+    Ascii,
+    /// British: `#` is the pound sign.
+    Uk,
+    /// DEC Special Graphics: lowercase letters and a few symbols become line-drawing
+    /// characters.
+    DecSpecialGraphics,
+}
+
+impl Charset {
+    /// The set named by the final byte of a designation such as `ESC ( 0`.
+    pub fn from_designator(byte: u8) -> Option<Self> {
+        match byte {
+            b'B' => Some(Self::Ascii),
+            b'A' => Some(Self::Uk),
+            b'0' => Some(Self::DecSpecialGraphics),
+            _ => None,
+        }
+    }
+
+    pub fn map(self, ch: char) -> char {
+        match self {
+            Self::Ascii => ch,
+            Self::Uk if ch == '#' => '£',
+            Self::Uk => ch,
+            Self::DecSpecialGraphics => match ch {
+                '_' => ' ',
+                '`' => '◆',
+                'a' => '▒',
+                'b' => '␉',
+                'c' => '␌',
+                'd' => '␍',
+                'e' => '␊',
+                'f' => '°',
+                'g' => '±',
+                'h' => '␤',
+                'i' => '␋',
+                'j' => '┘',
+                'k' => '┐',
+                'l' => '┌',
+                'm' => '└',
+                'n' => '┼',
+                'o' => '⎺',
+                'p' => '⎻',
+                'q' => '─',
+                'r' => '⎼',
+                's' => '⎽',
+                't' => '├',
+                'u' => '┤',
+                'v' => '┴',
+                'w' => '┬',
+                'x' => '│',
+                'y' => '≤',
+                'z' => '≥',
+                '{' => 'π',
+                '|' => '≠',
+                '}' => '£',
+                '~' => '·',
+                _ => ch,
+            },
+        }
+    }
+}
+
+/// The G0–G3 slots and which of them printed characters are translated through.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Charsets {
+    pub slots: [Charset; 4],
+    /// The slot shifted in with SI/SO/LS2/LS3.
+    pub active: usize,
+    /// A slot chosen with SS2/SS3 for the next character only.
+    pub single_shift: Option<usize>,
+}
+
+impl Charsets {
+    /// Translates a printed character, consuming any pending single shift.
+    pub fn translate(&mut self, ch: char) -> char {
+        let slot = self.single_shift.take().unwrap_or(self.active);
+        // Only the 94 printable ASCII characters are remapped
+        if ch.is_ascii_graphic() {
+            self.slots[slot].map(ch)
+        } else {
+            ch
+        }
+    }
+}
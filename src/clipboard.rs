@@ -0,0 +1,65 @@
+use arboard::{GetExtLinux, LinuxClipboardKind, SetExtLinux};
+
+/// The selections OSC 52 can address.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClipboardTarget {
+    Clipboard,
+    Primary,
+}
+
+impl ClipboardTarget {
+    /// The OSC 52 selection parameter naming this target.
+    pub fn parameter(self) -> char {
+        match self {
+            Self::Clipboard => 'c',
+            Self::Primary => 'p',
+        }
+    }
+
+    fn kind(self) -> LinuxClipboardKind {
+        match self {
+            Self::Clipboard => LinuxClipboardKind::Clipboard,
+            Self::Primary => LinuxClipboardKind::Primary,
+        }
+    }
+}
+
+/// The system clipboard. Without a reachable clipboard (e.g. no display server) reads
+/// return nothing and writes are dropped.
+pub struct Clipboard {
+    inner: Option<arboard::Clipboard>,
+}
+
+impl Clipboard {
+    pub fn new() -> Self {
+        let inner = match arboard::Clipboard::new() {
+            Ok(clipboard) => Some(clipboard),
+            Err(e) => {
+                eprintln!("Clipboard unavailable: {}", e);
+                None
+            }
+        };
+        Self { inner }
+    }
+
+    pub fn get(&mut self, target: ClipboardTarget) -> Option<String> {
+        let clipboard = self.inner.as_mut()?;
+        match clipboard.get().clipboard(target.kind()).text() {
+            Ok(text) => Some(text),
+            Err(arboard::Error::ContentNotAvailable) => Some(String::new()),
+            Err(e) => {
+                eprintln!("Failed to read clipboard: {}", e);
+                None
+            }
+        }
+    }
+
+    pub fn set(&mut self, target: ClipboardTarget, text: String) {
+        let Some(clipboard) = self.inner.as_mut() else {
+            return;
+        };
+        if let Err(e) = clipboard.set().clipboard(target.kind()).text(text) {
+            eprintln!("Failed to write clipboard: {}", e);
+        }
+    }
+}
@@ -0,0 +1,190 @@
+use crate::terminal::{CursorShape, CursorStyle};
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+/// Whether programs may read the clipboard with OSC 52.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClipboardReadPolicy {
+    Deny,
+    /// Ask in the window each time.
+    Ask,
+    Allow,
+}
+
+pub struct Config {
+    pub shell: Option<String>,
+    pub shell_args: Vec<String>,
+    /// Jump back to the live screen when the program writes output.
+    pub scroll_on_output: bool,
+    /// Lines scrolled per mouse wheel notch.
+    pub scroll_lines: usize,
+    /// Rows of scrollback kept.
+    pub scrollback_lines: usize,
+    /// Memory, in bytes, that compressed scrollback may use before older parts of it move
+    /// to a temporary file.
+    pub scrollback_max_bytes: usize,
+    /// Column width (1 or 2) of East Asian ambiguous-width characters.
+    pub ambiguous_width: usize,
+    /// Program that Ctrl+click runs with a hyperlink's URI appended.
+    pub link_opener: String,
+    pub link_opener_args: Vec<String>,
+    pub clipboard_read: ClipboardReadPolicy,
+    /// Largest clipboard text, in bytes, that OSC 52 may write or read.
+    pub clipboard_max_bytes: usize,
+    /// Ask before pasting several lines into a program without bracketed paste.
+    pub confirm_multiline_paste: bool,
+    /// The cursor used until a program picks another with DECSCUSR.
+    pub cursor_style: CursorStyle,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            shell: None,
+            shell_args: Vec::new(),
+            scroll_on_output: true,
+            scroll_lines: 3,
+            scrollback_lines: 10_000,
+            scrollback_max_bytes: 64 << 20,
+            ambiguous_width: 1,
+            link_opener: "xdg-open".to_string(),
+            link_opener_args: Vec::new(),
+            clipboard_read: ClipboardReadPolicy::Ask,
+            clipboard_max_bytes: 1 << 20,
+            confirm_multiline_paste: true,
+            cursor_style: CursorStyle::default(),
+        }
+    }
+}
+
+impl Config {
+    /// Loads `$XDG_CONFIG_HOME/vulkan-terminal/config` (or `~/.config/...`).
+    /// The file is a list of `key = value` lines; `#` starts a comment.
+    pub fn load() -> Self {
+        let mut config = Self::default();
+
+        let Some(path) = Self::path() else {
+            return config;
+        };
+        let Ok(contents) = fs::read_to_string(&path) else {
+            return config;
+        };
+
+        for (line_number, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let Some((key, value)) = line.split_once('=') else {
+                eprintln!(
+                    "{}:{}: expected `key = value`",
+                    path.display(),
+                    line_number + 1
+                );
+                continue;
+            };
+
+            if let Err(e) = config.set(key.trim(), value.trim()) {
+                eprintln!("{}:{}: {}", path.display(), line_number + 1, e);
+            }
+        }
+
+        config
+    }
+
+    fn path() -> Option<PathBuf> {
+        let base = env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+        Some(base.join("vulkan-terminal").join("config"))
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "shell" => {
+                let mut words = value.split_whitespace().map(str::to_string);
+                self.shell = words.next();
+                self.shell_args = words.collect();
+            }
+            "scroll_on_output" => self.scroll_on_output = parse_bool(value)?,
+            "scroll_lines" => {
+                self.scroll_lines = value
+                    .parse()
+                    .map_err(|_| format!("expected a number, got `{}`", value))?;
+            }
+            "scrollback_lines" => {
+                self.scrollback_lines = value
+                    .parse()
+                    .map_err(|_| format!("expected a number, got `{}`", value))?;
+            }
+            "scrollback_max_bytes" => {
+                self.scrollback_max_bytes = value
+                    .parse()
+                    .map_err(|_| format!("expected a number, got `{}`", value))?;
+            }
+            "link_opener" => {
+                let mut words = value.split_whitespace().map(str::to_string);
+                let Some(program) = words.next() else {
+                    return Err("expected a program".to_string());
+                };
+                self.link_opener = program;
+                self.link_opener_args = words.collect();
+            }
+            "clipboard_read" => {
+                self.clipboard_read = match value {
+                    "deny" => ClipboardReadPolicy::Deny,
+                    "ask" => ClipboardReadPolicy::Ask,
+                    "allow" => ClipboardReadPolicy::Allow,
+                    _ => return Err(format!("expected deny, ask or allow, got `{}`", value)),
+                };
+            }
+            "clipboard_max_bytes" => {
+                self.clipboard_max_bytes = value
+                    .parse()
+                    .map_err(|_| format!("expected a number, got `{}`", value))?;
+            }
+            "confirm_multiline_paste" => self.confirm_multiline_paste = parse_bool(value)?,
+            "cursor_shape" => {
+                self.cursor_style.shape = match value {
+                    "block" => CursorShape::Block,
+                    "underline" => CursorShape::Underline,
+                    "bar" => CursorShape::Bar,
+                    _ => return Err(format!("expected block, underline or bar, got `{}`", value)),
+                };
+            }
+            "cursor_blink" => self.cursor_style.blinking = parse_bool(value)?,
+            "ambiguous_width" => {
+                self.ambiguous_width = match value {
+                    "1" => 1,
+                    "2" => 2,
+                    _ => return Err(format!("expected 1 or 2, got `{}`", value)),
+                };
+            }
+            _ => return Err(format!("unknown key `{}`", key)),
+        }
+        Ok(())
+    }
+
+    /// The program to run on the PTY: the configured shell, then `$SHELL`, then `/bin/sh`.
+    pub fn shell_command(&self) -> (String, Vec<String>) {
+        if let Some(shell) = &self.shell {
+            return (shell.clone(), self.shell_args.clone());
+        }
+
+        let shell = env::var("SHELL")
+            .ok()
+            .filter(|shell| !shell.is_empty())
+            .unwrap_or_else(|| "/bin/sh".to_string());
+        (shell, Vec::new())
+    }
+}
+
+fn parse_bool(value: &str) -> Result<bool, String> {
+    match value {
+        "true" | "yes" | "on" => Ok(true),
+        "false" | "no" | "off" => Ok(false),
+        _ => Err(format!("expected true or false, got `{}`", value)),
+    }
+}
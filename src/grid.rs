@@ -0,0 +1,295 @@
+use crate::scrollback::{BLOCK_ROWS, ColdHistory};
+use crate::terminal::TerminalCell;
+use std::collections::VecDeque;
+use std::ops::{Deref, DerefMut, Index, IndexMut};
+
+/// One line of the grid or the scrollback.
+#[derive(Clone, Debug, Default)]
+pub struct Row {
+    pub cells: Vec<TerminalCell>,
+    /// Set when the text continues on the next row because of auto-wrap rather than a newline.
+    pub wrapped: bool,
+}
+
+impl Row {
+    pub fn new(width: usize) -> Self {
+        Self {
+            cells: vec![TerminalCell::default(); width],
+            wrapped: false,
+        }
+    }
+
+    /// Fills the row with `cell`, ending any soft wrap.
+    pub fn clear(&mut self, cell: TerminalCell) {
+        self.cells.fill(cell);
+        self.wrapped = false;
+    }
+}
+
+impl Deref for Row {
+    type Target = [TerminalCell];
+
+    fn deref(&self) -> &Self::Target {
+        &self.cells
+    }
+}
+
+impl DerefMut for Row {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.cells
+    }
+}
+
+/// Scrollback rows kept as cells, on top of the cold history. Older rows are compressed
+/// a block at a time.
+const HOT_HISTORY: usize = 1024;
+
+/// A screen and its scrollback. The screen and the most recent history share one ring
+/// buffer of rows: history, oldest first, followed by the `height` rows on screen.
+/// Scrolling the whole screen moves a row from one end to the other instead of moving
+/// the rest, reusing allocations of rows dropped or compressed earlier. Older history is
+/// compressed in `ColdHistory`.
+#[derive(Default)]
+pub struct Grid {
+    rows: VecDeque<Row>,
+    cold: ColdHistory,
+    /// Rows that left the ring, reused for new ones.
+    spare: Vec<Row>,
+    /// Shown for cold rows that couldn't be read.
+    blank: Row,
+    width: usize,
+    height: usize,
+    max_history: usize,
+    /// Rows dropped from the top of the history so far. Adding it to a row's index gives
+    /// an id that stays the same while older rows are dropped.
+    dropped: u64,
+}
+
+impl Grid {
+    pub fn new(width: usize, height: usize, max_history: usize) -> Self {
+        let mut grid = Self {
+            max_history,
+            ..Self::default()
+        };
+        grid.set_rows(Vec::new(), vec![Row::new(width); height], width);
+        grid
+    }
+
+    /// Sets how many rows of history are kept and how many bytes of compressed history may
+    /// stay in memory before the oldest blocks are spilled to disk.
+    pub fn set_limits(&mut self, max_history: usize, max_memory: usize) {
+        self.max_history = max_history;
+        self.dropped += self.cold.set_max_memory(max_memory) as u64;
+        self.trim_history();
+    }
+
+    /// Takes out the rows kept as cells: the recent history and the screen. The grid is
+    /// left without a screen until `set_rows`.
+    pub fn take_rows(&mut self) -> (Vec<Row>, Vec<Row>) {
+        let mut history: Vec<Row> = std::mem::take(&mut self.rows).into();
+        let screen = history.split_off(history.len() - self.height);
+        self.height = 0;
+        (history, screen)
+    }
+
+    /// Takes out the compressed history to rewrap it, leaving it empty. Rows added back
+    /// with `push_cold_block` and `set_rows` get ids after those of the old history.
+    pub fn take_cold(&mut self) -> ColdHistory {
+        self.dropped += self.history_len() as u64;
+        self.cold.take()
+    }
+
+    /// Compresses `rows`, `BLOCK_ROWS` of them, after the other compressed history, e.g. to
+    /// refill it after `take_cold`. Only valid while the rows kept as cells are taken out.
+    pub fn push_cold_block(&mut self, rows: &[Row]) {
+        self.dropped += self.cold.push_block(rows) as u64;
+    }
+
+    /// Replaces the rows kept as cells with `history`, oldest first, and `screen`, which
+    /// are `width` columns wide.
+    pub fn set_rows(&mut self, history: Vec<Row>, screen: Vec<Row>, width: usize) {
+        self.rows = history.into();
+        self.height = screen.len();
+        self.rows.extend(screen);
+        self.width = width;
+        self.blank = Row::new(width);
+        self.cold.clear_cache();
+        self.trim_history();
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn history_len(&self) -> usize {
+        self.cold.len() + self.hot_history_len()
+    }
+
+    /// The id of the oldest row of history, or of the top screen row without history.
+    pub fn first_row_id(&self) -> u64 {
+        self.dropped
+    }
+
+    fn hot_history_len(&self) -> usize {
+        self.rows.len() - self.height
+    }
+
+    pub fn clear_history(&mut self) {
+        self.dropped += self.history_len() as u64;
+        self.cold.clear();
+        let history = self.hot_history_len();
+        let rows: Vec<Row> = self.rows.drain(..history).collect();
+        rows.into_iter().for_each(|row| self.recycle(row));
+    }
+
+    fn recycle(&mut self, row: Row) {
+        if self.spare.len() < BLOCK_ROWS {
+            self.spare.push(row);
+        }
+    }
+
+    /// Drops history beyond `max_history`, oldest first, and compresses the oldest rows
+    /// kept as cells once there are a block more than `HOT_HISTORY`.
+    fn trim_history(&mut self) {
+        let excess = self.history_len().saturating_sub(self.max_history);
+        let from_cold = excess.min(self.cold.len());
+        self.dropped += excess as u64;
+        self.cold.drop_oldest(from_cold);
+        for _ in from_cold..excess {
+            let row = self.rows.pop_front().unwrap();
+            self.recycle(row);
+        }
+
+        while self.hot_history_len() >= HOT_HISTORY + BLOCK_ROWS {
+            let block: Vec<Row> = self.rows.drain(..BLOCK_ROWS).collect();
+            self.dropped += self.cold.push_block(&block) as u64;
+            block.into_iter().for_each(|row| self.recycle(row));
+        }
+    }
+
+    /// Decompresses the cold rows in view when scrolled back `offset` rows, so
+    /// `viewport` can show them. Needed after anything that changes what is in view.
+    pub fn prepare_viewport(&mut self, offset: usize) {
+        let start = self.history_len() - offset.min(self.history_len());
+        let end = (start + self.height).min(self.cold.len());
+        if start < end {
+            self.cold.load(start..end, self.width);
+        }
+    }
+
+    /// The `height` rows in view when scrolled back `offset` rows into the history.
+    pub fn viewport(&self, offset: usize) -> impl Iterator<Item = &Row> {
+        let start = self.history_len() - offset.min(self.history_len());
+        let cold = self.cold.len();
+        (start..start + self.height).map(move |index| match index.checked_sub(cold) {
+            Some(hot) => &self.rows[hot],
+            None => self.cold.cached_row(index).unwrap_or(&self.blank),
+        })
+    }
+
+    /// How many of the oldest history rows are compressed. Their contents no longer change.
+    pub fn cold_len(&self) -> usize {
+        self.cold.len()
+    }
+
+    /// Calls `f` with every row from index `start` on, counted from the oldest history
+    /// row, decompressing cold history a block at a time. Cold rows may be shorter than
+    /// `width`.
+    pub fn for_each_row_from(&self, start: usize, mut f: impl FnMut(&Row)) {
+        let cold = self.cold.len();
+        if start < cold {
+            self.cold.for_each_row_from(start, &self.blank, &mut f);
+        }
+        let hot = start.saturating_sub(cold).min(self.rows.len());
+        self.rows.range(hot..).for_each(f);
+    }
+
+    /// The rows on screen.
+    pub fn screen_mut(&mut self) -> impl Iterator<Item = &mut Row> {
+        let history = self.hot_history_len();
+        self.rows.range_mut(history..)
+    }
+
+    /// Crops or pads the screen to `width` x `height`, trimming or adding rows at the
+    /// bottom. History rows are cropped or padded to the new width too.
+    pub fn resize(&mut self, width: usize, height: usize) {
+        let history = self.hot_history_len();
+        self.rows.resize(history + height, Row::new(width));
+        for row in &mut self.rows {
+            row.cells.resize(width, TerminalCell::default());
+        }
+        self.width = width;
+        self.height = height;
+        self.blank = Row::new(width);
+        self.cold.clear_cache();
+    }
+
+    /// Scrolls screen rows `top..=bottom` up by `count`, filling the rows that appear at
+    /// the bottom with `blank`. With `save_history` and a region covering the whole
+    /// screen, the rows scrolled off the top move into the history.
+    pub fn scroll_up(
+        &mut self,
+        top: usize,
+        bottom: usize,
+        count: usize,
+        blank: TerminalCell,
+        save_history: bool,
+    ) {
+        let count = count.min(bottom + 1 - top);
+        let base = self.hot_history_len();
+
+        if top == 0 && bottom + 1 == self.height {
+            let save_history = save_history && self.max_history > 0;
+            for _ in 0..count {
+                let mut row = if save_history {
+                    self.spare.pop().unwrap_or_else(|| Row::new(self.width))
+                } else {
+                    self.rows.remove(self.hot_history_len()).unwrap()
+                };
+                row.cells.resize(self.width, blank);
+                row.clear(blank);
+                self.rows.push_back(row);
+            }
+            if save_history {
+                self.trim_history();
+            }
+            return;
+        }
+
+        for y in top..bottom + 1 - count {
+            self.rows.swap(base + y, base + y + count);
+        }
+        for y in bottom + 1 - count..=bottom {
+            self.rows[base + y].clear(blank);
+        }
+    }
+
+    /// Scrolls screen rows `top..=bottom` down by `count`, filling the rows that appear at
+    /// the top with `blank`.
+    pub fn scroll_down(&mut self, top: usize, bottom: usize, count: usize, blank: TerminalCell) {
+        let count = count.min(bottom + 1 - top);
+        let base = self.hot_history_len();
+        for y in (top + count..=bottom).rev() {
+            self.rows.swap(base + y, base + y - count);
+        }
+        for y in top..top + count {
+            self.rows[base + y].clear(blank);
+        }
+    }
+}
+
+/// Indexes rows on screen; row 0 is the top of the screen, not of the history.
+impl Index<usize> for Grid {
+    type Output = Row;
+
+    fn index(&self, y: usize) -> &Row {
+        &self.rows[self.hot_history_len() + y]
+    }
+}
+
+impl IndexMut<usize> for Grid {
+    fn index_mut(&mut self, y: usize) -> &mut Row {
+        let history = self.hot_history_len();
+        &mut self.rows[history + y]
+    }
+}
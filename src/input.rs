@@ -0,0 +1,287 @@
+use crate::terminal::{Modes, MouseEncoding, MouseTracking};
+use winit::event::{ElementState, KeyEvent, MouseButton};
+use winit::keyboard::{Key, ModifiersState, NamedKey};
+
+/// Translates a key press into the byte sequence an xterm-compatible
+/// terminal would send to the child process.
+pub fn encode_key(event: &KeyEvent, modifiers: ModifiersState, modes: Modes) -> Option<Vec<u8>> {
+    if event.state != ElementState::Pressed {
+        return None;
+    }
+
+    let modifier_param = modifier_parameter(modifiers);
+
+    match &event.logical_key {
+        Key::Named(named) => encode_named_key(*named, modifiers, modifier_param, modes),
+        Key::Character(text) => {
+            let mut bytes = Vec::new();
+            if modifiers.alt_key() {
+                bytes.push(0x1b);
+            }
+
+            if modifiers.control_key()
+                && let Some(control) = control_byte(text)
+            {
+                bytes.push(control);
+                return Some(bytes);
+            }
+
+            let text = event.text.as_deref().unwrap_or(text);
+            bytes.extend_from_slice(text.as_bytes());
+            Some(bytes)
+        }
+        _ => None,
+    }
+}
+
+/// A mouse event to report to the program.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MouseReport {
+    Press(MouseButton),
+    Release(MouseButton),
+    /// Pointer motion, with the button held during it if any.
+    Motion(Option<MouseButton>),
+    WheelUp,
+    WheelDown,
+}
+
+/// Where a mouse event happened: the 0-based cell and the pixel offset from the grid origin.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MousePosition {
+    pub column: usize,
+    pub row: usize,
+    pub x: usize,
+    pub y: usize,
+}
+
+/// Encodes a mouse event for the active tracking mode and encoding, or returns `None`
+/// when the mode doesn't report it.
+pub fn encode_mouse(
+    report: MouseReport,
+    position: MousePosition,
+    modifiers: ModifiersState,
+    tracking: MouseTracking,
+    encoding: MouseEncoding,
+) -> Option<Vec<u8>> {
+    let reported = match (tracking, report) {
+        (MouseTracking::None, _) => false,
+        (MouseTracking::X10, report) => {
+            matches!(
+                report,
+                MouseReport::Press(_) | MouseReport::WheelUp | MouseReport::WheelDown
+            )
+        }
+        (MouseTracking::Normal, report) => !matches!(report, MouseReport::Motion(_)),
+        (MouseTracking::ButtonEvent, MouseReport::Motion(button)) => button.is_some(),
+        (MouseTracking::ButtonEvent | MouseTracking::AnyEvent, _) => true,
+    };
+    if !reported {
+        return None;
+    }
+
+    let mut code = match report {
+        MouseReport::Press(button) | MouseReport::Release(button) => button_code(button)?,
+        MouseReport::Motion(Some(button)) => button_code(button)? + 32,
+        MouseReport::Motion(None) => 3 + 32,
+        MouseReport::WheelUp => 64,
+        MouseReport::WheelDown => 65,
+    };
+    if tracking != MouseTracking::X10 {
+        if modifiers.shift_key() {
+            code += 4;
+        }
+        if modifiers.alt_key() {
+            code += 8;
+        }
+        if modifiers.control_key() {
+            code += 16;
+        }
+    }
+    let release = matches!(report, MouseReport::Release(_));
+
+    match encoding {
+        MouseEncoding::Default => {
+            // Releases don't say which button; coordinates are 1-based and offset by 32
+            let code = if release { (code & !3) | 3 } else { code };
+            let column = u8::try_from(position.column + 1 + 32).ok()?;
+            let row = u8::try_from(position.row + 1 + 32).ok()?;
+            Some(vec![0x1b, b'[', b'M', code as u8 + 32, column, row])
+        }
+        MouseEncoding::Sgr | MouseEncoding::SgrPixels => {
+            let (x, y) = if encoding == MouseEncoding::Sgr {
+                (position.column + 1, position.row + 1)
+            } else {
+                (position.x + 1, position.y + 1)
+            };
+            let action = if release { 'm' } else { 'M' };
+            Some(format!("\x1b[<{};{};{}{}", code, x, y, action).into_bytes())
+        }
+    }
+}
+
+fn button_code(button: MouseButton) -> Option<u16> {
+    match button {
+        MouseButton::Left => Some(0),
+        MouseButton::Middle => Some(1),
+        MouseButton::Right => Some(2),
+        MouseButton::Back => Some(128),
+        MouseButton::Forward => Some(129),
+        _ => None,
+    }
+}
+
+/// Prepares pasted text for the child process. End-of-paste markers and control
+/// characters other than tab and newline are removed so the paste cannot run commands
+/// or escape bracketed paste; newlines are sent as carriage returns, like Enter.
+pub fn encode_paste(text: &str, bracketed: bool) -> Vec<u8> {
+    let text = text
+        .replace("\x1b[200~", "")
+        .replace("\x1b[201~", "")
+        .replace("\r\n", "\r");
+    let text: String = text
+        .chars()
+        .filter(|&ch| !ch.is_control() || matches!(ch, '\t' | '\n' | '\r'))
+        .map(|ch| if ch == '\n' { '\r' } else { ch })
+        .collect();
+
+    let mut bytes = Vec::with_capacity(text.len() + 12);
+    if bracketed {
+        bytes.extend_from_slice(b"\x1b[200~");
+    }
+    bytes.extend_from_slice(text.as_bytes());
+    if bracketed {
+        bytes.extend_from_slice(b"\x1b[201~");
+    }
+    bytes
+}
+
+fn encode_named_key(
+    key: NamedKey,
+    modifiers: ModifiersState,
+    modifier_param: Option<u8>,
+    modes: Modes,
+) -> Option<Vec<u8>> {
+    // DECCKM switches unmodified cursor keys to SS3
+    let cursor_key = if modes.application_cursor_keys {
+        ss3_key
+    } else {
+        cursor_key
+    };
+    let bytes: Vec<u8> = match key {
+        NamedKey::Enter => {
+            let enter: &[u8] = if modes.newline { b"\r\n" } else { b"\r" };
+            if modifiers.alt_key() {
+                [b"\x1b", enter].concat()
+            } else {
+                enter.to_vec()
+            }
+        }
+        NamedKey::Backspace => {
+            if modifiers.control_key() {
+                vec![0x08]
+            } else if modifiers.alt_key() {
+                vec![0x1b, 0x7f]
+            } else {
+                vec![0x7f]
+            }
+        }
+        NamedKey::Tab => {
+            if modifiers.shift_key() {
+                b"\x1b[Z".to_vec()
+            } else {
+                b"\t".to_vec()
+            }
+        }
+        NamedKey::Escape => vec![0x1b],
+        NamedKey::Space => {
+            if modifiers.control_key() {
+                vec![0x00]
+            } else if modifiers.alt_key() {
+                b"\x1b ".to_vec()
+            } else {
+                b" ".to_vec()
+            }
+        }
+        NamedKey::ArrowUp => cursor_key(b'A', modifier_param),
+        NamedKey::ArrowDown => cursor_key(b'B', modifier_param),
+        NamedKey::ArrowRight => cursor_key(b'C', modifier_param),
+        NamedKey::ArrowLeft => cursor_key(b'D', modifier_param),
+        NamedKey::Home => cursor_key(b'H', modifier_param),
+        NamedKey::End => cursor_key(b'F', modifier_param),
+        NamedKey::Insert => tilde_key(2, modifier_param),
+        NamedKey::Delete => tilde_key(3, modifier_param),
+        NamedKey::PageUp => tilde_key(5, modifier_param),
+        NamedKey::PageDown => tilde_key(6, modifier_param),
+        NamedKey::F1 => ss3_key(b'P', modifier_param),
+        NamedKey::F2 => ss3_key(b'Q', modifier_param),
+        NamedKey::F3 => ss3_key(b'R', modifier_param),
+        NamedKey::F4 => ss3_key(b'S', modifier_param),
+        NamedKey::F5 => tilde_key(15, modifier_param),
+        NamedKey::F6 => tilde_key(17, modifier_param),
+        NamedKey::F7 => tilde_key(18, modifier_param),
+        NamedKey::F8 => tilde_key(19, modifier_param),
+        NamedKey::F9 => tilde_key(20, modifier_param),
+        NamedKey::F10 => tilde_key(21, modifier_param),
+        NamedKey::F11 => tilde_key(23, modifier_param),
+        NamedKey::F12 => tilde_key(24, modifier_param),
+        _ => return None,
+    };
+    Some(bytes)
+}
+
+/// xterm encodes modifiers as `1 + (shift | alt << 1 | ctrl << 2)`, omitted when none are held.
+fn modifier_parameter(modifiers: ModifiersState) -> Option<u8> {
+    let mut value = 0;
+    if modifiers.shift_key() {
+        value |= 1;
+    }
+    if modifiers.alt_key() {
+        value |= 2;
+    }
+    if modifiers.control_key() {
+        value |= 4;
+    }
+    (value != 0).then_some(value + 1)
+}
+
+fn cursor_key(final_byte: u8, modifier_param: Option<u8>) -> Vec<u8> {
+    match modifier_param {
+        Some(param) => format!("\x1b[1;{}{}", param, final_byte as char).into_bytes(),
+        None => vec![0x1b, b'[', final_byte],
+    }
+}
+
+fn ss3_key(final_byte: u8, modifier_param: Option<u8>) -> Vec<u8> {
+    match modifier_param {
+        Some(param) => format!("\x1b[1;{}{}", param, final_byte as char).into_bytes(),
+        None => vec![0x1b, b'O', final_byte],
+    }
+}
+
+fn tilde_key(number: u8, modifier_param: Option<u8>) -> Vec<u8> {
+    match modifier_param {
+        Some(param) => format!("\x1b[{};{}~", number, param).into_bytes(),
+        None => format!("\x1b[{}~", number).into_bytes(),
+    }
+}
+
+/// Maps Ctrl+<key> to its C0 control byte, e.g. Ctrl+C to ETX.
+fn control_byte(text: &str) -> Option<u8> {
+    let mut chars = text.chars();
+    let ch = chars.next()?;
+    if chars.next().is_some() {
+        return None;
+    }
+
+    match ch.to_ascii_lowercase() {
+        ch @ 'a'..='z' => Some(ch as u8 - b'a' + 1),
+        '@' | '2' => Some(0x00),
+        '[' | '3' => Some(0x1b),
+        '\\' | '4' => Some(0x1c),
+        ']' | '5' => Some(0x1d),
+        '^' | '6' => Some(0x1e),
+        '_' | '-' | '7' => Some(0x1f),
+        '?' | '8' => Some(0x7f),
+        _ => None,
+    }
+}
@@ -1,42 +1,648 @@
+mod charset;
+mod clipboard;
+mod config;
+mod grid;
+mod input;
+mod palette;
+mod parser;
+mod pty;
+mod scrollback;
+mod search;
 mod terminal;
+mod terminfo;
 mod text_renderer;
 mod vulkan;
 
-use std::time::Instant;
-use terminal::TerminalState;
-use text_renderer::TextRenderer;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use clipboard::{Clipboard, ClipboardTarget};
+use config::{ClipboardReadPolicy, Config};
+use input::{MousePosition, MouseReport};
+use pty::{Pty, PtyEvent};
+use search::{Highlight, Match, Search, SearchOptions};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+use terminal::{
+    Attribute, CellWidth, ClipboardRequest, CursorShape, Damage, MouseEncoding, MouseTracking,
+    TerminalCell, TerminalColor, TerminalState, UnderlineStyle,
+};
+use text_renderer::{Batch, TextRenderer};
 use vulkan::VulkanContext;
 use winit::{
     application::ApplicationHandler,
-    dpi::LogicalSize,
-    event::{ElementState, KeyEvent, WindowEvent},
-    event_loop::{ActiveEventLoop, ControlFlow, EventLoop},
-    keyboard::{KeyCode, PhysicalKey},
-    window::{Window, WindowId},
+    dpi::{LogicalSize, PhysicalPosition, PhysicalSize},
+    event::{ElementState, KeyEvent, MouseButton, MouseScrollDelta, WindowEvent},
+    event_loop::{ActiveEventLoop, ControlFlow, EventLoop, EventLoopProxy},
+    keyboard::{Key, ModifiersState, NamedKey},
+    window::{CursorIcon, Window, WindowId},
 };
 
+const MARGIN_X: f32 = 20.0;
+const MARGIN_Y: f32 = 20.0;
+
+const DEFAULT_TITLE: &str = "Vulkan Terminal";
+/// Minimum time between window title updates, so a program rewriting its title in a
+/// tight loop can't flood the compositor.
+const TITLE_UPDATE_INTERVAL: Duration = Duration::from_millis(100);
+/// How long the cursor and blinking text stay in each blink phase.
+const BLINK_INTERVAL: Duration = Duration::from_millis(500);
+/// Minimum time between rescans of an open search while output arrives.
+const SEARCH_UPDATE_INTERVAL: Duration = Duration::from_millis(200);
+/// Most PTY output parsed per event loop iteration, so a flood of output can't starve
+/// input and drawing.
+const PTY_BYTES_PER_ITERATION: usize = 1 << 20;
+
+/// The geometry of one viewport row, kept between frames so only damaged rows are rebuilt.
+#[derive(Default)]
+struct RowGeometry {
+    background: Batch,
+    foreground: Batch,
+    /// Whether the row has blinking text, which must be rebuilt when the blink phase flips.
+    blinks: bool,
+}
+
+/// Everything a frame depends on besides the terminal's contents. A frame with no damage
+/// and the same state as the last one is skipped.
+#[derive(Clone, PartialEq)]
+struct FrameState {
+    blink_visible: bool,
+    hovered_link: u32,
+    focused: bool,
+    confirmation: Option<String>,
+    search: Option<String>,
+    highlights: Vec<Highlight>,
+    window_size: PhysicalSize<u32>,
+}
+
+/// A yes/no question shown over the bottom row. `y` confirms; any other key declines.
+struct Confirmation {
+    message: String,
+    action: ConfirmedAction,
+}
+
+enum ConfirmedAction {
+    ClipboardRead {
+        target: ClipboardTarget,
+        bell_terminated: bool,
+    },
+    Paste(String),
+}
+
 struct VulkanTerminalApp {
     window: Option<Window>,
     vulkan_context: Option<VulkanContext>,
     text_renderer: Option<TextRenderer>,
     terminal_state: TerminalState,
-    last_frame_time: Instant,
-    cursor_blink_timer: f32,
-    cursor_visible: bool,
+    config: Config,
+    pty: Option<Pty>,
+    clipboard: Clipboard,
+    confirmation: Option<Confirmation>,
+    search: Option<Search>,
+    /// Options the next search starts with: those of the last one.
+    search_options: SearchOptions,
+    /// Set when the terminal changed since the open search last ran.
+    search_stale: bool,
+    last_search: Instant,
+    modifiers: ModifiersState,
+    /// Fractional wheel movement not yet turned into whole lines.
+    wheel_remainder: f32,
+    /// Mouse position inside the window, `None` while the pointer is outside it.
+    mouse_position: Option<PhysicalPosition<f64>>,
+    /// The button held down, for reporting drags.
+    mouse_button_held: Option<MouseButton>,
+    /// Where the last motion report was sent, so motion within a cell isn't repeated.
+    last_mouse_report: Option<MousePosition>,
+    /// The title last passed to `Window::set_title` and when.
+    window_title: String,
+    last_title_update: Instant,
+    /// When the blink cycle started; the cursor and blinking text restart visible from here.
+    blink_epoch: Instant,
+    /// Each viewport row's geometry from the frames it was last rebuilt in.
+    row_geometry: Vec<RowGeometry>,
+    overlay: Batch,
+    /// Batches not yet uploaded to the GPU, in the order passed to `upload_batches`.
+    pending_uploads: Vec<bool>,
+    /// What the last drawn frame depended on besides the terminal's damage.
+    last_frame: Option<FrameState>,
+    /// Wakes the event loop when the PTY has output.
+    proxy: EventLoopProxy<()>,
+    /// Whether the window has keyboard focus; unfocused windows show a steady hollow cursor.
+    focused: bool,
 }
 
 impl VulkanTerminalApp {
-    fn new() -> Self {
-        let terminal_state = TerminalState::new(80, 24);
+    fn new(config: Config, proxy: EventLoopProxy<()>) -> Self {
+        let mut terminal_state = TerminalState::new(80, 24);
+        terminal_state.scroll_on_output = config.scroll_on_output;
+        terminal_state.set_scrollback_limits(config.scrollback_lines, config.scrollback_max_bytes);
+        terminal_state.ambiguous_wide = config.ambiguous_width == 2;
+        terminal_state.set_clipboard_max_bytes(config.clipboard_max_bytes);
+        terminal_state.default_cursor_style = config.cursor_style;
+        terminal_state.cursor_style = config.cursor_style;
 
         Self {
             window: None,
             vulkan_context: None,
             text_renderer: None,
             terminal_state,
-            last_frame_time: Instant::now(),
-            cursor_blink_timer: 0.0,
-            cursor_visible: true,
+            config,
+            pty: None,
+            clipboard: Clipboard::new(),
+            confirmation: None,
+            search: None,
+            search_options: SearchOptions::default(),
+            search_stale: false,
+            last_search: Instant::now(),
+            modifiers: ModifiersState::empty(),
+            wheel_remainder: 0.0,
+            mouse_position: None,
+            mouse_button_held: None,
+            last_mouse_report: None,
+            window_title: DEFAULT_TITLE.to_string(),
+            last_title_update: Instant::now(),
+            blink_epoch: Instant::now(),
+            row_geometry: Vec::new(),
+            overlay: Batch::default(),
+            pending_uploads: Vec::new(),
+            last_frame: None,
+            proxy,
+            focused: true,
+        }
+    }
+
+    fn spawn_shell(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let (program, args) = self.config.shell_command();
+        let proxy = self.proxy.clone();
+        let pty = Pty::spawn(
+            &program,
+            &args,
+            self.terminal_state.width as u16,
+            self.terminal_state.height as u16,
+            move || {
+                let _ = proxy.send_event(());
+            },
+        )?;
+        self.pty = Some(pty);
+        Ok(())
+    }
+
+    /// Feeds pending PTY output into the terminal, up to `PTY_BYTES_PER_ITERATION` before
+    /// waking the event loop again for the rest. Returns false once the child has exited.
+    fn process_pty_events(&mut self) -> bool {
+        let Some(pty) = &self.pty else {
+            return true;
+        };
+
+        let mut processed = 0;
+        while let Some(event) = pty.try_recv() {
+            match event {
+                PtyEvent::Output(bytes) => {
+                    self.terminal_state.process_output(&bytes);
+                    processed += bytes.len();
+                    self.search_stale = self.search.is_some();
+                    if let Some(window) = &self.window {
+                        window.request_redraw();
+                    }
+                }
+                PtyEvent::Exited => return false,
+            }
+            if processed >= PTY_BYTES_PER_ITERATION {
+                let _ = self.proxy.send_event(());
+                break;
+            }
+        }
+        true
+    }
+
+    fn write_to_pty(&mut self, bytes: &[u8]) {
+        if let Some(pty) = &mut self.pty
+            && let Err(e) = pty.write(bytes)
+        {
+            eprintln!("Failed to write to PTY: {}", e);
+        }
+    }
+
+    /// Carries out OSC 52 requests. Reads go through `clipboard_read`; while a question
+    /// is already on screen further reads are declined.
+    fn handle_clipboard_requests(&mut self) {
+        for request in std::mem::take(&mut self.terminal_state.clipboard_requests) {
+            match request {
+                ClipboardRequest::Store { target, text } => self.clipboard.set(target, text),
+                ClipboardRequest::Load {
+                    target,
+                    bell_terminated,
+                } => match self.config.clipboard_read {
+                    ClipboardReadPolicy::Deny => {}
+                    ClipboardReadPolicy::Allow => {
+                        self.answer_clipboard_read(target, bell_terminated)
+                    }
+                    ClipboardReadPolicy::Ask if self.confirmation.is_none() => {
+                        self.confirmation = Some(Confirmation {
+                            message: "Allow the program to read the clipboard? [y/N]".to_string(),
+                            action: ConfirmedAction::ClipboardRead {
+                                target,
+                                bell_terminated,
+                            },
+                        });
+                    }
+                    ClipboardReadPolicy::Ask => {}
+                },
+                ClipboardRequest::Oversized => eprintln!(
+                    "Ignoring OSC 52 write of more than {} bytes",
+                    self.config.clipboard_max_bytes
+                ),
+            }
+        }
+    }
+
+    fn answer_clipboard_read(&mut self, target: ClipboardTarget, bell_terminated: bool) {
+        let Some(text) = self.clipboard.get(target) else {
+            return;
+        };
+        if text.len() > self.config.clipboard_max_bytes {
+            eprintln!(
+                "Refusing OSC 52 read of more than {} bytes",
+                self.config.clipboard_max_bytes
+            );
+            return;
+        }
+
+        let terminator = if bell_terminated { "\x07" } else { "\x1b\\" };
+        let reply = format!(
+            "\x1b]52;{};{}{}",
+            target.parameter(),
+            BASE64.encode(text),
+            terminator
+        );
+        self.write_to_pty(reply.as_bytes());
+    }
+
+    /// Answers the pending confirmation with a key press.
+    fn handle_confirmation_key(&mut self, key_event: &KeyEvent) {
+        // Modifiers alone neither confirm nor decline, so Shift+Y works
+        if matches!(
+            key_event.logical_key,
+            Key::Named(NamedKey::Shift | NamedKey::Control | NamedKey::Alt | NamedKey::Super)
+        ) {
+            return;
+        }
+
+        let Some(confirmation) = self.confirmation.take() else {
+            return;
+        };
+        let confirmed = matches!(&key_event.logical_key, Key::Character(text) if text.eq_ignore_ascii_case("y"));
+        if !confirmed {
+            return;
+        }
+
+        match confirmation.action {
+            ConfirmedAction::ClipboardRead {
+                target,
+                bell_terminated,
+            } => self.answer_clipboard_read(target, bell_terminated),
+            ConfirmedAction::Paste(text) => self.paste_text(&text),
+        }
+    }
+
+    /// Ctrl+Shift+V pastes the clipboard, Shift+Insert the primary selection.
+    fn handle_paste_key(&mut self, key_event: &KeyEvent) -> bool {
+        let target = match &key_event.logical_key {
+            Key::Character(text)
+                if self.modifiers.control_key()
+                    && self.modifiers.shift_key()
+                    && text.eq_ignore_ascii_case("v") =>
+            {
+                ClipboardTarget::Clipboard
+            }
+            Key::Named(NamedKey::Insert)
+                if self.modifiers.shift_key() && !self.modifiers.control_key() =>
+            {
+                ClipboardTarget::Primary
+            }
+            _ => return false,
+        };
+
+        if let Some(text) = self.clipboard.get(target) {
+            self.paste(text);
+        }
+        true
+    }
+
+    /// Pastes `text`, first asking for confirmation when several lines would go to a
+    /// program that hasn't enabled bracketed paste, where each line runs as a command.
+    fn paste(&mut self, text: String) {
+        let lines = text
+            .trim_end_matches(['\r', '\n'])
+            .replace("\r\n", "\n")
+            .split(['\r', '\n'])
+            .count();
+        if lines > 1
+            && self.config.confirm_multiline_paste
+            && !self.terminal_state.modes.bracketed_paste
+        {
+            self.confirmation = Some(Confirmation {
+                message: format!("Paste {} lines? [y/N]", lines),
+                action: ConfirmedAction::Paste(text),
+            });
+            return;
+        }
+        self.paste_text(&text);
+    }
+
+    fn paste_text(&mut self, text: &str) {
+        let bytes = input::encode_paste(text, self.terminal_state.modes.bracketed_paste);
+        self.terminal_state.scroll_to_bottom();
+        self.write_to_pty(&bytes);
+    }
+
+    /// The title the window should have: the terminal's, or the default until one is set.
+    fn desired_title(&self) -> &str {
+        match self.terminal_state.title.as_str() {
+            "" => DEFAULT_TITLE,
+            title => title,
+        }
+    }
+
+    /// Applies a title change from the terminal, at most once per `TITLE_UPDATE_INTERVAL`.
+    /// A change that arrives too soon is picked up by a later call.
+    fn update_window_title(&mut self) {
+        let title = self.desired_title();
+        if title == self.window_title || self.last_title_update.elapsed() < TITLE_UPDATE_INTERVAL {
+            return;
+        }
+
+        if let Some(window) = &self.window {
+            window.set_title(title);
+        }
+        self.window_title = title.to_string();
+        self.last_title_update = Instant::now();
+    }
+
+    /// Ctrl+Shift+F opens the search bar, or closes it when open.
+    fn handle_search_toggle_key(&mut self, key_event: &KeyEvent) -> bool {
+        let toggle = matches!(&key_event.logical_key, Key::Character(text)
+            if self.modifiers.control_key()
+                && self.modifiers.shift_key()
+                && text.eq_ignore_ascii_case("f"));
+        if !toggle {
+            return false;
+        }
+
+        match self.search {
+            Some(_) => self.close_search(),
+            None => self.search = Some(Search::new(self.search_options)),
+        }
+        true
+    }
+
+    /// Closes the search bar, keeping its options for the next search.
+    fn close_search(&mut self) {
+        if let Some(search) = self.search.take() {
+            self.search_options = search.options;
+        }
+    }
+
+    /// Edits the query while the search bar is open. Enter selects the next older match
+    /// and Shift+Enter the next newer one; Alt+R, Alt+C and Alt+S toggle regular
+    /// expressions, case sensitivity and smart case; Escape closes the bar.
+    fn handle_search_key(&mut self, key_event: &KeyEvent) {
+        if self.handle_scrollback_key(key_event) {
+            return;
+        }
+        let Some(search) = &mut self.search else {
+            return;
+        };
+
+        match &key_event.logical_key {
+            Key::Named(NamedKey::Escape) => {
+                self.close_search();
+                return;
+            }
+            Key::Named(NamedKey::Enter) => {
+                let selected = search.select_next(!self.modifiers.shift_key());
+                self.reveal_match(selected);
+                return;
+            }
+            Key::Named(NamedKey::Backspace) => {
+                search.query.pop();
+            }
+            Key::Character(text) if self.modifiers.alt_key() => {
+                let options = &mut search.options;
+                match text.to_ascii_lowercase().as_str() {
+                    "r" => options.regex = !options.regex,
+                    "c" => options.case_sensitive = !options.case_sensitive,
+                    "s" => options.smart_case = !options.smart_case,
+                    _ => return,
+                }
+            }
+            _ if self.modifiers.control_key() || self.modifiers.alt_key() => return,
+            _ => match &key_event.text {
+                Some(text) if !text.chars().any(char::is_control) => search.query.push_str(text),
+                _ => return,
+            },
+        }
+        self.update_search();
+    }
+
+    /// Reruns the search after its query or options changed and selects the match
+    /// nearest the bottom of the view.
+    fn update_search(&mut self) {
+        let bottom = self.terminal_state.viewport_top_row() + self.search_rows() as u64 - 1;
+        let Some(search) = &mut self.search else {
+            return;
+        };
+        search.run(&self.terminal_state);
+        let selected = search.select_from(bottom);
+        self.search_stale = false;
+        self.last_search = Instant::now();
+        self.reveal_match(selected);
+    }
+
+    /// Reruns an open search over output that arrived since it last ran, at most once per
+    /// `SEARCH_UPDATE_INTERVAL`. The selection stays where it was.
+    fn refresh_search(&mut self) {
+        if !self.search_stale || self.last_search.elapsed() < SEARCH_UPDATE_INTERVAL {
+            return;
+        }
+        if let Some(search) = &mut self.search {
+            search.refresh(&self.terminal_state);
+            if let Some(window) = &self.window {
+                window.request_redraw();
+            }
+        }
+        self.search_stale = false;
+        self.last_search = Instant::now();
+    }
+
+    /// Viewport rows not covered by the search bar.
+    fn search_rows(&self) -> usize {
+        self.terminal_state.height.saturating_sub(1).max(1)
+    }
+
+    fn reveal_match(&mut self, selected: Option<Match>) {
+        if let Some(selected) = selected {
+            let rows = self.search_rows();
+            self.terminal_state.scroll_to_row(selected.start.0, rows);
+        }
+    }
+
+    fn cell_size(&self) -> (f32, f32) {
+        match &self.text_renderer {
+            Some(text_renderer) => (text_renderer.fixed_advance, text_renderer.line_height),
+            None => (9.0, 16.0),
+        }
+    }
+
+    /// The viewport cell (column, row) under a window position, if it is on the grid.
+    fn cell_at(&self, position: PhysicalPosition<f64>) -> Option<(usize, usize)> {
+        let (char_width, char_height) = self.cell_size();
+        let x = (position.x as f32 - MARGIN_X) / char_width;
+        let y = (position.y as f32 - MARGIN_Y) / char_height;
+        if x < 0.0 || y < 0.0 {
+            return None;
+        }
+
+        let (column, row) = (x as usize, y as usize);
+        (column < self.terminal_state.width && row < self.terminal_state.height)
+            .then_some((column, row))
+    }
+
+    /// The visible cell under the mouse pointer.
+    fn hovered_cell(&self) -> Option<TerminalCell> {
+        let (column, row) = self.cell_at(self.mouse_position?)?;
+        let visible_cells = self.terminal_state.get_visible_cells();
+        visible_cells.get(row)?.get(column).copied()
+    }
+
+    /// The hyperlink id of the cell under the mouse, 0 when there is none.
+    fn hovered_hyperlink(&self) -> u32 {
+        self.hovered_cell().map_or(0, |cell| cell.hyperlink)
+    }
+
+    /// Whether mouse events go to the program. Holding Shift keeps them local.
+    fn mouse_reporting(&self) -> bool {
+        self.terminal_state.mouse_tracking != MouseTracking::None && !self.modifiers.shift_key()
+    }
+
+    /// The cell and grid-relative pixel under a window position, clamped to the grid so
+    /// drags past the edge still report.
+    fn mouse_grid_position(&self, position: PhysicalPosition<f64>) -> MousePosition {
+        let (char_width, char_height) = self.cell_size();
+        let x = (position.x as f32 - MARGIN_X).max(0.0);
+        let y = (position.y as f32 - MARGIN_Y).max(0.0);
+        MousePosition {
+            column: ((x / char_width) as usize).min(self.terminal_state.width - 1),
+            row: ((y / char_height) as usize).min(self.terminal_state.height - 1),
+            x: x as usize,
+            y: y as usize,
+        }
+    }
+
+    /// Sends a mouse event to the program if the active tracking mode reports it.
+    fn report_mouse(&mut self, report: MouseReport) {
+        let Some(position) = self.mouse_position else {
+            return;
+        };
+        let position = self.mouse_grid_position(position);
+
+        if let MouseReport::Motion(_) = report {
+            let same_spot = self.last_mouse_report.is_some_and(|last| {
+                if self.terminal_state.mouse_encoding == MouseEncoding::SgrPixels {
+                    (last.x, last.y) == (position.x, position.y)
+                } else {
+                    (last.column, last.row) == (position.column, position.row)
+                }
+            });
+            if same_spot {
+                return;
+            }
+        }
+        self.last_mouse_report = Some(position);
+
+        if let Some(bytes) = input::encode_mouse(
+            report,
+            position,
+            self.modifiers,
+            self.terminal_state.mouse_tracking,
+            self.terminal_state.mouse_encoding,
+        ) {
+            self.write_to_pty(&bytes);
+        }
+    }
+
+    fn handle_focus(&mut self, focused: bool) {
+        self.focused = focused;
+        // Restart the blink so the cursor is shown right away either way
+        self.blink_epoch = Instant::now();
+
+        if self.terminal_state.modes.focus_reporting {
+            let report: &[u8] = if focused { b"\x1b[I" } else { b"\x1b[O" };
+            self.write_to_pty(report);
+        }
+    }
+
+    fn handle_cursor_moved(&mut self, position: PhysicalPosition<f64>) {
+        self.mouse_position = Some(position);
+        if self.mouse_reporting() {
+            self.report_mouse(MouseReport::Motion(self.mouse_button_held));
+        }
+
+        let icon = if self.hovered_hyperlink() != 0 {
+            CursorIcon::Pointer
+        } else {
+            CursorIcon::Text
+        };
+        if let Some(window) = &self.window {
+            window.set_cursor(icon);
+        }
+    }
+
+    fn handle_mouse_input(&mut self, state: ElementState, button: MouseButton) {
+        match state {
+            ElementState::Pressed => self.mouse_button_held = Some(button),
+            ElementState::Released if self.mouse_button_held == Some(button) => {
+                self.mouse_button_held = None;
+            }
+            ElementState::Released => {}
+        }
+
+        if self.mouse_reporting() {
+            let report = match state {
+                ElementState::Pressed => MouseReport::Press(button),
+                ElementState::Released => MouseReport::Release(button),
+            };
+            self.report_mouse(report);
+            return;
+        }
+
+        if state != ElementState::Pressed || button != MouseButton::Left {
+            return;
+        }
+
+        if self.modifiers.control_key()
+            && let Some(cell) = self.hovered_cell()
+            && let Some(uri) = self.terminal_state.hyperlink_uri(&cell)
+        {
+            self.open_hyperlink(uri);
+        }
+    }
+
+    /// Launches the configured opener with the URI as its last argument.
+    fn open_hyperlink(&self, uri: &str) {
+        let result = Command::new(&self.config.link_opener)
+            .args(&self.config.link_opener_args)
+            .arg(uri)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .spawn();
+
+        match result {
+            // Reap the opener once it exits so it doesn't linger as a zombie
+            Ok(mut child) => {
+                thread::spawn(move || child.wait());
+            }
+            Err(e) => eprintln!("Failed to run {}: {}", self.config.link_opener, e),
         }
     }
 
@@ -60,255 +666,238 @@ impl VulkanTerminalApp {
         Ok(())
     }
 
+    /// Whether anything on screen blinks right now: a blinking cursor in view or blinking
+    /// text. Unfocused windows don't blink.
+    fn blink_active(&self) -> bool {
+        let state = &self.terminal_state;
+        let cursor_blinks = state.modes.show_cursor
+            && state.cursor_style.blinking
+            && state.cursor_viewport_row().is_some();
+        self.focused && (cursor_blinks || self.row_geometry.iter().any(|row| row.blinks))
+    }
+
+    /// The blink phase at `now`: visible for the first `BLINK_INTERVAL` of each cycle.
+    fn blink_visible(&self, now: Instant) -> bool {
+        !self.blink_active() || self.blink_periods(now).is_multiple_of(2)
+    }
+
+    fn blink_periods(&self, now: Instant) -> u32 {
+        let elapsed = now.duration_since(self.blink_epoch).as_millis();
+        (elapsed / BLINK_INTERVAL.as_millis()) as u32
+    }
+
+    fn next_blink(&self, now: Instant) -> Instant {
+        self.blink_epoch + BLINK_INTERVAL * (self.blink_periods(now) + 1)
+    }
+
+    /// Draws a frame if anything changed since the last one. Only rows the terminal
+    /// reports as damaged are rebuilt and uploaded; the cursor, scrollbar and prompt are
+    /// redrawn as an overlay on every frame.
     fn draw(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        if let (Some(vulkan_context), Some(text_renderer)) =
+        let Some(window) = &self.window else {
+            return Ok(());
+        };
+        let terminal_state = &self.terminal_state;
+        let frame = FrameState {
+            blink_visible: self.blink_visible(Instant::now()),
+            hovered_link: self.hovered_hyperlink(),
+            focused: self.focused,
+            confirmation: self.confirmation.as_ref().map(|c| c.message.clone()),
+            search: self.search.as_ref().map(Search::status),
+            highlights: self.search.as_ref().map_or_else(Vec::new, |search| {
+                search.highlights(
+                    terminal_state.viewport_top_row(),
+                    terminal_state.height,
+                    terminal_state.width,
+                )
+            }),
+            window_size: window.inner_size(),
+        };
+        let (Some(vulkan_context), Some(text_renderer)) =
             (&mut self.vulkan_context, &mut self.text_renderer)
-        {
-            let now = Instant::now();
-            let delta_time = now.duration_since(self.last_frame_time).as_secs_f32();
-            self.last_frame_time = now;
-
-            self.cursor_blink_timer += delta_time;
-            if self.cursor_blink_timer >= 0.5 {
-                self.cursor_visible = !self.cursor_visible;
-                self.cursor_blink_timer = 0.0;
-            }
+        else {
+            return Ok(());
+        };
 
-            let terminal_state = &self.terminal_state;
-            let cursor_visible = self.cursor_visible;
-            let window_size = self.window.as_ref().unwrap().inner_size();
-            let screen_dimensions = [window_size.width as f32, window_size.height as f32];
-
-            let command_pool = vulkan_context.command_pool;
-            let graphics_queue = vulkan_context.graphics_queue;
-            let physical_device = vulkan_context.physical_device;
-            let instance = vulkan_context.instance.clone();
-
-            vulkan_context.draw_frame(|command_buffer| {
-                let mut vertices = Vec::new();
-                let mut indices = Vec::new();
-
-                // Add the test triangle vertices
-                vertices.extend_from_slice(&[
-                    text_renderer::Vertex {
-                        position: [100.0, 100.0], // Top-left
-                        tex_coord: [0.0, 0.0],
-                        color: [1.0, 0.0, 0.0, 1.0], // Red
-                    },
-                    text_renderer::Vertex {
-                        position: [200.0, 100.0], // Top-right
-                        tex_coord: [1.0, 0.0],
-                        color: [0.0, 1.0, 0.0, 1.0], // Green
-                    },
-                    text_renderer::Vertex {
-                        position: [150.0, 200.0], // Bottom-center
-                        tex_coord: [0.5, 1.0],
-                        color: [0.0, 0.0, 1.0, 1.0], // Blue
-                    },
-                ]);
-                indices.extend_from_slice(&[0, 1, 2]);
-
-                // Render terminal content
-                let char_width = 9.0;
-                let char_height = 16.0;
-                let margin_x = 20.0;
-                let margin_y = 220.0;
-
-                for (y, row) in terminal_state.get_visible_cells().iter().enumerate() {
-                    for (x, cell) in row.iter().enumerate() {
-                        if cell.character != ' ' {
-                            let screen_x = margin_x + (x as f32 * char_width);
-                            let screen_y = margin_y + (y as f32 * char_height);
-                            text_renderer.render_text_to_buffer(
-                                &mut vertices,
-                                &mut indices,
-                                &cell.character.to_string(),
-                                screen_x,
-                                screen_y,
-                                [1.0, 1.0, 1.0, 1.0],
-                                command_pool,
-                                graphics_queue,
-                                physical_device,
-                                &instance,
-                            )?;
-                        }
+        let height = self.terminal_state.height;
+        let mut dirty = vec![false; height];
+        match self.terminal_state.take_damage() {
+            Damage::Full => dirty.fill(true),
+            Damage::Rows(rows) => rows.into_iter().for_each(|row| dirty[row] = true),
+        }
+        match &self.last_frame {
+            Some(last) if self.row_geometry.len() == height => {
+                if last.hovered_link != frame.hovered_link {
+                    dirty.fill(true);
+                }
+                if last.blink_visible != frame.blink_visible {
+                    for (y, row) in self.row_geometry.iter().enumerate() {
+                        dirty[y] |= row.blinks;
                     }
                 }
-
-                // Render blinking cursor
-                if cursor_visible {
-                    let (cursor_x, cursor_y) = terminal_state.get_cursor_position();
-                    let screen_x = margin_x + (cursor_x as f32 * char_width);
-                    let screen_y = margin_y + (cursor_y as f32 * char_height);
-                    text_renderer.render_text_to_buffer(
-                        &mut vertices,
-                        &mut indices,
-                        "_",
-                        screen_x,
-                        screen_y,
-                        [1.0, 0.0, 0.0, 1.0],
-                        command_pool,
-                        graphics_queue,
-                        physical_device,
-                        &instance,
-                    )?;
+                if !dirty.contains(&true) && *last == frame {
+                    return Ok(());
                 }
+            }
+            _ => dirty.fill(true),
+        }
 
-                // Update buffers and draw
-                if !vertices.is_empty() {
-                    text_renderer.update_vertex_buffer(&vertices)?;
-                    text_renderer.update_index_buffer(&indices)?;
-
-                    unsafe {
-                        text_renderer.device.cmd_bind_pipeline(
-                            command_buffer,
-                            ash::vk::PipelineBindPoint::GRAPHICS,
-                            text_renderer.graphics_pipeline,
-                        );
-
-                        text_renderer.device.cmd_bind_vertex_buffers(
-                            command_buffer,
-                            0,
-                            &[text_renderer.vertex_buffer],
-                            &[0],
-                        );
-
-                        text_renderer.device.cmd_bind_index_buffer(
-                            command_buffer,
-                            text_renderer.index_buffer,
-                            0,
-                            ash::vk::IndexType::UINT16,
-                        );
-
-                        text_renderer.device.cmd_bind_descriptor_sets(
-                            command_buffer,
-                            ash::vk::PipelineBindPoint::GRAPHICS,
-                            text_renderer.pipeline_layout,
-                            0,
-                            &text_renderer.descriptor_sets,
-                            &[],
-                        );
-
-                        let push_constants = text_renderer::PushConstants { screen_dimensions };
-                        let push_constants_bytes = {
-                            std::slice::from_raw_parts(
-                                &push_constants as *const _ as *const u8,
-                                std::mem::size_of::<text_renderer::PushConstants>(),
-                            )
-                        };
-                        text_renderer.device.cmd_push_constants(
-                            command_buffer,
-                            text_renderer.pipeline_layout,
-                            ash::vk::ShaderStageFlags::VERTEX,
-                            0,
-                            push_constants_bytes,
-                        );
-
-                        text_renderer.device.cmd_draw_indexed(
-                            command_buffer,
-                            indices.len() as u32,
-                            1,
-                            0,
-                            0,
-                            0,
-                        );
-                    }
-                }
+        self.row_geometry.resize_with(height, RowGeometry::default);
+        let terminal_state = &self.terminal_state;
+        for (y, row) in terminal_state.get_visible_cells().into_iter().enumerate() {
+            if dirty[y] {
+                build_row(
+                    &mut self.row_geometry[y],
+                    text_renderer,
+                    vulkan_context,
+                    terminal_state,
+                    row,
+                    y,
+                    &frame,
+                )?;
+            }
+        }
+        build_overlay(
+            &mut self.overlay,
+            text_renderer,
+            vulkan_context,
+            terminal_state,
+            &frame,
+        )?;
 
-                Ok(())
-            })?;
+        // Backgrounds of every row go first so no row's glyphs are covered by the next one
+        let batch_count = 2 * height + 1;
+        if self.pending_uploads.len() != batch_count {
+            self.pending_uploads = vec![true; batch_count];
+        }
+        for (y, &row_dirty) in dirty.iter().enumerate() {
+            self.pending_uploads[y] |= row_dirty;
+            self.pending_uploads[height + y] |= row_dirty;
         }
+        self.pending_uploads[2 * height] = true;
+        let batches: Vec<&Batch> = self
+            .row_geometry
+            .iter()
+            .map(|row| &row.background)
+            .chain(self.row_geometry.iter().map(|row| &row.foreground))
+            .chain(std::iter::once(&self.overlay))
+            .collect();
+
+        let physical_device = vulkan_context.physical_device;
+        let instance = vulkan_context.instance.clone();
+        let screen_dimensions = [
+            frame.window_size.width as f32,
+            frame.window_size.height as f32,
+        ];
+        let clear_color = terminal_state.screen_background().as_array();
+        let pending_uploads = &mut self.pending_uploads;
+        vulkan_context.draw_frame(clear_color, |command_buffer| {
+            text_renderer.upload_batches(&batches, pending_uploads, physical_device, &instance)?;
+            pending_uploads.fill(false);
+            text_renderer.draw_batches(command_buffer, screen_dimensions);
+            Ok(())
+        })?;
+
+        self.last_frame = Some(frame);
         Ok(())
     }
 
     fn handle_keyboard_input(&mut self, key_event: KeyEvent) {
-        if key_event.state == ElementState::Pressed {
-            match key_event.physical_key {
-                PhysicalKey::Code(KeyCode::Enter) => {
-                    self.terminal_state.handle_input('\n');
-                }
-                PhysicalKey::Code(KeyCode::Backspace) => {
-                    self.terminal_state.handle_input('\x08');
-                }
-                PhysicalKey::Code(KeyCode::Tab) => {
-                    self.terminal_state.handle_input('\t');
-                }
-                PhysicalKey::Code(KeyCode::Space) => {
-                    self.terminal_state.handle_input(' ');
-                }
-                PhysicalKey::Code(code) => {
-                    if let Some(ch) = self.keycode_to_char(code) {
-                        self.terminal_state.handle_input(ch);
-                    }
-                }
-                _ => {}
+        if self.confirmation.is_some() {
+            if key_event.state == ElementState::Pressed {
+                self.handle_confirmation_key(&key_event);
             }
+            return;
         }
+
+        if key_event.state == ElementState::Pressed && self.handle_search_toggle_key(&key_event) {
+            return;
+        }
+        if self.search.is_some() {
+            if key_event.state == ElementState::Pressed {
+                self.handle_search_key(&key_event);
+            }
+            return;
+        }
+
+        if key_event.state == ElementState::Pressed
+            && (self.handle_paste_key(&key_event) || self.handle_scrollback_key(&key_event))
+        {
+            return;
+        }
+
+        let Some(bytes) = input::encode_key(&key_event, self.modifiers, self.terminal_state.modes)
+        else {
+            return;
+        };
+
+        self.terminal_state.scroll_to_bottom();
+        self.write_to_pty(&bytes);
     }
 
-    fn keycode_to_char(&self, keycode: KeyCode) -> Option<char> {
-        match keycode {
-            KeyCode::KeyA => Some('a'),
-            KeyCode::KeyB => Some('b'),
-            KeyCode::KeyC => Some('c'),
-            KeyCode::KeyD => Some('d'),
-            KeyCode::KeyE => Some('e'),
-            KeyCode::KeyF => Some('f'),
-            KeyCode::KeyG => Some('g'),
-            KeyCode::KeyH => Some('h'),
-            KeyCode::KeyI => Some('i'),
-            KeyCode::KeyJ => Some('j'),
-            KeyCode::KeyK => Some('k'),
-            KeyCode::KeyL => Some('l'),
-            KeyCode::KeyM => Some('m'),
-            KeyCode::KeyN => Some('n'),
-            KeyCode::KeyO => Some('o'),
-            KeyCode::KeyP => Some('p'),
-            KeyCode::KeyQ => Some('q'),
-            KeyCode::KeyR => Some('r'),
-            KeyCode::KeyS => Some('s'),
-            KeyCode::KeyT => Some('t'),
-            KeyCode::KeyU => Some('u'),
-            KeyCode::KeyV => Some('v'),
-            KeyCode::KeyW => Some('w'),
-            KeyCode::KeyX => Some('x'),
-            KeyCode::KeyY => Some('y'),
-            KeyCode::KeyZ => Some('z'),
-            KeyCode::Digit0 => Some('0'),
-            KeyCode::Digit1 => Some('1'),
-            KeyCode::Digit2 => Some('2'),
-            KeyCode::Digit3 => Some('3'),
-            KeyCode::Digit4 => Some('4'),
-            KeyCode::Digit5 => Some('5'),
-            KeyCode::Digit6 => Some('6'),
-            KeyCode::Digit7 => Some('7'),
-            KeyCode::Digit8 => Some('8'),
-            KeyCode::Digit9 => Some('9'),
-            KeyCode::Minus => Some('-'),
-            KeyCode::Equal => Some('='),
-            KeyCode::BracketLeft => Some('['),
-            KeyCode::BracketRight => Some(']'),
-            KeyCode::Backslash => Some('\\'),
-            KeyCode::Semicolon => Some(';'),
-            KeyCode::Quote => Some('\''),
-            KeyCode::Comma => Some(','),
-            KeyCode::Period => Some('.'),
-            KeyCode::Slash => Some('/'),
-            _ => None,
+    /// Shift+PageUp/PageDown/Home/End move through the scrollback instead of reaching the shell.
+    fn handle_scrollback_key(&mut self, key_event: &KeyEvent) -> bool {
+        if !self.modifiers.shift_key() || self.terminal_state.alternate_screen_active {
+            return false;
+        }
+
+        let page = self.terminal_state.height.saturating_sub(1).max(1) as isize;
+        match key_event.logical_key {
+            Key::Named(NamedKey::PageUp) => self.terminal_state.scroll_viewport(page),
+            Key::Named(NamedKey::PageDown) => self.terminal_state.scroll_viewport(-page),
+            Key::Named(NamedKey::Home) => self.terminal_state.scroll_to_top(),
+            Key::Named(NamedKey::End) => self.terminal_state.scroll_to_bottom(),
+            _ => return false,
+        }
+        true
+    }
+
+    /// Scrolls the scrollback, or reports one wheel event per line while the program
+    /// tracks the mouse.
+    fn handle_mouse_wheel(&mut self, delta: MouseScrollDelta) {
+        let reporting = self.mouse_reporting();
+        let lines = match delta {
+            MouseScrollDelta::LineDelta(_, y) if reporting => y,
+            MouseScrollDelta::LineDelta(_, y) => y * self.config.scroll_lines as f32,
+            MouseScrollDelta::PixelDelta(position) => {
+                let (_, char_height) = self.cell_size();
+                position.y as f32 / char_height
+            }
+        } + self.wheel_remainder;
+
+        let whole_lines = lines.trunc();
+        self.wheel_remainder = lines - whole_lines;
+
+        if reporting {
+            let report = if whole_lines > 0.0 {
+                MouseReport::WheelUp
+            } else {
+                MouseReport::WheelDown
+            };
+            for _ in 0..whole_lines.abs() as usize {
+                self.report_mouse(report);
+            }
+        } else {
+            self.terminal_state.scroll_viewport(whole_lines as isize);
         }
     }
 
     fn resize_terminal(&mut self, width: u32, height: u32) {
-        let char_width = 8.0;
-        let char_height = 16.0;
-        let margin_x = 20.0;
-        let margin_y = 20.0;
+        let (char_width, char_height) = self.cell_size();
+
+        let terminal_width = ((width as f32 - MARGIN_X * 2.0) / char_width) as usize;
+        let terminal_height = ((height as f32 - MARGIN_Y * 2.0) / char_height) as usize;
+        let terminal_width = terminal_width.max(1);
+        let terminal_height = terminal_height.max(1);
 
-        let terminal_width = ((width as f32 - margin_x) / char_width) as usize;
-        let terminal_height = ((height as f32 - margin_y) / char_height) as usize;
+        self.terminal_state.resize(terminal_width, terminal_height);
+        self.search_stale = self.search.is_some();
 
-        self.terminal_state
-            .resize(terminal_width.max(1), terminal_height.max(1));
+        if let Some(pty) = &self.pty
+            && let Err(e) = pty.resize(terminal_width as u16, terminal_height as u16)
+        {
+            eprintln!("Failed to resize PTY: {}", e);
+        }
     }
 }
 
@@ -322,29 +911,337 @@ impl Drop for VulkanTerminalApp {
     }
 }
 
+/// Rebuilds the geometry of viewport row `y`.
+fn build_row(
+    geometry: &mut RowGeometry,
+    text_renderer: &mut TextRenderer,
+    vulkan_context: &VulkanContext,
+    terminal_state: &TerminalState,
+    row: &[TerminalCell],
+    y: usize,
+    frame: &FrameState,
+) -> Result<(), Box<dyn std::error::Error>> {
+    geometry.background.clear();
+    geometry.foreground.clear();
+    geometry.blinks = row.iter().any(|cell| cell.attributes.has(Attribute::Blink));
+
+    let char_width = text_renderer.fixed_advance;
+    let char_height = text_renderer.line_height;
+    for (x, cell) in row.iter().enumerate() {
+        if cell.bg_color == palette::Color::DefaultBackground
+            && !cell.attributes.has(Attribute::Inverse)
+        {
+            continue;
+        }
+        let (_, bg_color) = terminal_state.cell_colors(cell);
+        text_renderer.render_rect_to_buffer(
+            &mut geometry.background.vertices,
+            &mut geometry.background.indices,
+            MARGIN_X + (x as f32 * char_width),
+            MARGIN_Y + (y as f32 * char_height),
+            char_width,
+            char_height,
+            bg_color.as_array(),
+        );
+    }
+
+    for (x, cell) in row.iter().enumerate() {
+        // Blinking text shares the cursor's blink phase
+        if cell.attributes.has(Attribute::Blink) && !frame.blink_visible {
+            continue;
+        }
+        let (fg_color, _) = terminal_state.cell_colors(cell);
+        push_cell_foreground(
+            &mut geometry.foreground,
+            text_renderer,
+            vulkan_context,
+            terminal_state,
+            cell,
+            (x, y),
+            fg_color,
+            frame.hovered_link,
+        )?;
+    }
+    Ok(())
+}
+
+/// Appends a cell's glyph and decorations in `fg_color`. Spacers and hidden cells draw
+/// nothing.
+#[allow(clippy::too_many_arguments)]
+fn push_cell_foreground(
+    batch: &mut Batch,
+    text_renderer: &mut TextRenderer,
+    vulkan_context: &VulkanContext,
+    terminal_state: &TerminalState,
+    cell: &TerminalCell,
+    (x, y): (usize, usize),
+    fg_color: TerminalColor,
+    hovered_link: u32,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let columns = match cell.width {
+        CellWidth::Single => 1,
+        CellWidth::Wide => 2,
+        CellWidth::WideSpacer | CellWidth::LeadingWideSpacer => return Ok(()),
+    };
+    let attributes = cell.attributes;
+    if attributes.has(Attribute::Hidden) {
+        return Ok(());
+    }
+
+    let char_width = text_renderer.fixed_advance;
+    let char_height = text_renderer.line_height;
+    let ascent = text_renderer.ascent;
+    let thickness = text_renderer.line_thickness;
+    let screen_x = MARGIN_X + (x as f32 * char_width);
+    let cell_top = MARGIN_Y + (y as f32 * char_height);
+    let screen_y = cell_top + ascent;
+    let cell_width = columns as f32 * char_width;
+
+    if cell.character != ' ' {
+        text_renderer.render_cell_to_buffer(
+            &mut batch.vertices,
+            &mut batch.indices,
+            &terminal_state.cell_text(cell),
+            columns,
+            screen_x,
+            screen_y,
+            fg_color.as_array(),
+            vulkan_context.command_pool,
+            vulkan_context.graphics_queue,
+            vulkan_context.physical_device,
+            &vulkan_context.instance,
+        )?;
+    }
+
+    // Hovered links are underlined unless they already are
+    let underline = if attributes.underline() == UnderlineStyle::None
+        && cell.hyperlink != 0
+        && cell.hyperlink == hovered_link
+    {
+        UnderlineStyle::Single
+    } else {
+        attributes.underline()
+    };
+    if underline != UnderlineStyle::None {
+        let underline_color = terminal_state.underline_color(cell, fg_color);
+        text_renderer.render_underline_to_buffer(
+            &mut batch.vertices,
+            &mut batch.indices,
+            underline,
+            screen_x,
+            screen_y,
+            cell_width,
+            underline_color.as_array(),
+        );
+    }
+    if attributes.has(Attribute::Strikethrough) {
+        text_renderer.render_rect_to_buffer(
+            &mut batch.vertices,
+            &mut batch.indices,
+            screen_x,
+            screen_y - (ascent * 0.3).round(),
+            cell_width,
+            thickness,
+            fg_color.as_array(),
+        );
+    }
+    if attributes.has(Attribute::Overline) {
+        text_renderer.render_rect_to_buffer(
+            &mut batch.vertices,
+            &mut batch.indices,
+            screen_x,
+            cell_top,
+            cell_width,
+            thickness,
+            fg_color.as_array(),
+        );
+    }
+    Ok(())
+}
+
+/// Rebuilds what is drawn over the rows: search matches, the scrollback indicator, the
+/// cursor, the search bar and any pending question.
+fn build_overlay(
+    overlay: &mut Batch,
+    text_renderer: &mut TextRenderer,
+    vulkan_context: &VulkanContext,
+    terminal_state: &TerminalState,
+    frame: &FrameState,
+) -> Result<(), Box<dyn std::error::Error>> {
+    overlay.clear();
+    let char_width = text_renderer.fixed_advance;
+    let char_height = text_renderer.line_height;
+    let window_width = frame.window_size.width as f32;
+
+    // Search matches, tinting the text under them
+    for highlight in &frame.highlights {
+        let color = if highlight.current {
+            [1.0, 0.5, 0.0, 0.5]
+        } else {
+            [1.0, 0.85, 0.0, 0.3]
+        };
+        text_renderer.render_rect_to_buffer(
+            &mut overlay.vertices,
+            &mut overlay.indices,
+            MARGIN_X + highlight.columns.start as f32 * char_width,
+            MARGIN_Y + highlight.y as f32 * char_height,
+            highlight.columns.len() as f32 * char_width,
+            char_height,
+            color,
+        );
+    }
+
+    // Scrollback position indicator in the right margin
+    if terminal_state.scroll_offset > 0 {
+        let history_rows = terminal_state.history_len() as f32;
+        let total_rows = history_rows + terminal_state.height as f32;
+        let track_height = terminal_state.height as f32 * char_height;
+        let thumb_height = (track_height * terminal_state.height as f32 / total_rows).max(8.0);
+        let thumb_top = MARGIN_Y
+            + (track_height - thumb_height)
+                * (1.0 - terminal_state.scroll_offset as f32 / history_rows);
+        text_renderer.render_rect_to_buffer(
+            &mut overlay.vertices,
+            &mut overlay.indices,
+            window_width - MARGIN_X * 0.6,
+            thumb_top,
+            MARGIN_X * 0.3,
+            thumb_height,
+            [1.0, 1.0, 1.0, 0.4],
+        );
+    }
+
+    let cursor_style = terminal_state.cursor_style;
+    let cursor_shown = terminal_state.modes.show_cursor
+        && (frame.blink_visible || !cursor_style.blinking || !frame.focused);
+    if cursor_shown && let Some(cursor_y) = terminal_state.cursor_viewport_row() {
+        let (cursor_x, _) = terminal_state.get_cursor_position();
+        let cell = terminal_state.get_visible_cells()[cursor_y][cursor_x];
+        let columns = if cell.width == CellWidth::Wide { 2 } else { 1 };
+        let cursor_color = terminal_state.palette.cursor_color().as_array();
+        let left = MARGIN_X + (cursor_x as f32 * char_width);
+        let top = MARGIN_Y + (cursor_y as f32 * char_height);
+        let width = columns as f32 * char_width;
+        let thickness = text_renderer.line_thickness;
+        let beam = (char_width / 8.0).round().max(thickness);
+
+        // A focused block is filled, with the text under it redrawn inverted; bar and
+        // underline cursors sit on top of the text; unfocused windows show a hollow block
+        let rects = if !frame.focused {
+            vec![
+                (left, top, width, thickness),
+                (left, top + char_height - thickness, width, thickness),
+                (left, top, thickness, char_height),
+                (left + width - thickness, top, thickness, char_height),
+            ]
+        } else {
+            match cursor_style.shape {
+                CursorShape::Block => vec![(left, top, width, char_height)],
+                CursorShape::Bar => vec![(left, top, beam, char_height)],
+                CursorShape::Underline => vec![(left, top + char_height - beam, width, beam)],
+            }
+        };
+        for (x, y, w, h) in rects {
+            text_renderer.render_rect_to_buffer(
+                &mut overlay.vertices,
+                &mut overlay.indices,
+                x,
+                y,
+                w,
+                h,
+                cursor_color,
+            );
+        }
+        if frame.focused
+            && cursor_style.shape == CursorShape::Block
+            && (frame.blink_visible || !cell.attributes.has(Attribute::Blink))
+        {
+            let (_, bg_color) = terminal_state.cell_colors(&cell);
+            push_cell_foreground(
+                overlay,
+                text_renderer,
+                vulkan_context,
+                terminal_state,
+                &cell,
+                (cursor_x, cursor_y),
+                bg_color,
+                frame.hovered_link,
+            )?;
+        }
+    }
+
+    // The search bar and any pending question are drawn over the bottom row, the
+    // question on top
+    for message in frame.search.iter().chain(&frame.confirmation) {
+        push_bottom_bar(
+            overlay,
+            text_renderer,
+            vulkan_context,
+            terminal_state,
+            window_width,
+            message,
+        )?;
+    }
+    Ok(())
+}
+
+/// Appends a bar across the window over the bottom row, showing `message`.
+fn push_bottom_bar(
+    overlay: &mut Batch,
+    text_renderer: &mut TextRenderer,
+    vulkan_context: &VulkanContext,
+    terminal_state: &TerminalState,
+    window_width: f32,
+    message: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let top = MARGIN_Y + (terminal_state.height - 1) as f32 * text_renderer.line_height;
+    text_renderer.render_rect_to_buffer(
+        &mut overlay.vertices,
+        &mut overlay.indices,
+        0.0,
+        top,
+        window_width,
+        text_renderer.line_height,
+        [0.2, 0.2, 0.2, 1.0],
+    );
+    text_renderer.render_text_to_buffer(
+        &mut overlay.vertices,
+        &mut overlay.indices,
+        message,
+        MARGIN_X,
+        top + text_renderer.ascent,
+        [1.0, 1.0, 1.0, 1.0],
+        vulkan_context.command_pool,
+        vulkan_context.graphics_queue,
+        vulkan_context.physical_device,
+        &vulkan_context.instance,
+    )?;
+    Ok(())
+}
+
 impl ApplicationHandler for VulkanTerminalApp {
     fn resumed(&mut self, event_loop: &ActiveEventLoop) {
         let window_attributes = Window::default_attributes()
-            .with_title("Vulkan Terminal")
+            .with_title(DEFAULT_TITLE)
             .with_inner_size(LogicalSize::new(800, 600))
             .with_resizable(true);
 
         let window = event_loop.create_window(window_attributes).unwrap();
-
         let window_size = window.inner_size();
-        self.resize_terminal(window_size.width, window_size.height);
-
-        self.terminal_state
-            .write_str("Welcome to Vulkan Terminal!\n");
-        self.terminal_state
-            .write_str("Type 'help' for available commands.\n");
-        self.terminal_state.write_str("$ ");
 
         self.window = Some(window);
 
         if let Err(e) = self.init_vulkan() {
             eprintln!("Failed to initialize Vulkan: {}", e);
             event_loop.exit();
+            return;
+        }
+
+        self.resize_terminal(window_size.width, window_size.height);
+
+        if let Err(e) = self.spawn_shell() {
+            eprintln!("Failed to spawn shell: {}", e);
+            event_loop.exit();
         }
     }
 
@@ -354,6 +1251,14 @@ impl ApplicationHandler for VulkanTerminalApp {
         _window_id: WindowId,
         event: WindowEvent,
     ) {
+        // Input and window changes may change what's on screen; `draw` skips the frame
+        // when they didn't
+        if event != WindowEvent::RedrawRequested
+            && let Some(window) = &self.window
+        {
+            window.request_redraw();
+        }
+
         match event {
             WindowEvent::CloseRequested => {
                 event_loop.exit();
@@ -361,34 +1266,80 @@ impl ApplicationHandler for VulkanTerminalApp {
             WindowEvent::Resized(new_size) => {
                 self.resize_terminal(new_size.width, new_size.height);
             }
+            WindowEvent::ModifiersChanged(modifiers) => {
+                self.modifiers = modifiers.state();
+            }
             WindowEvent::KeyboardInput { event, .. } => {
                 self.handle_keyboard_input(event);
             }
+            WindowEvent::MouseWheel { delta, .. } => {
+                self.handle_mouse_wheel(delta);
+            }
+            WindowEvent::CursorMoved { position, .. } => {
+                self.handle_cursor_moved(position);
+            }
+            WindowEvent::Focused(focused) => {
+                self.handle_focus(focused);
+            }
+            WindowEvent::CursorLeft { .. } => {
+                self.mouse_position = None;
+            }
+            WindowEvent::MouseInput { state, button, .. } => {
+                self.handle_mouse_input(state, button);
+            }
             WindowEvent::RedrawRequested => {
                 if let Err(e) = self.draw() {
                     eprintln!("Draw error: {}", e);
                 }
-
-                if let Some(window) = &self.window {
-                    window.request_redraw();
-                }
             }
             _ => {}
         }
     }
 
-    fn about_to_wait(&mut self, _event_loop: &ActiveEventLoop) {
-        if let Some(window) = &self.window {
+    fn about_to_wait(&mut self, event_loop: &ActiveEventLoop) {
+        if !self.process_pty_events() {
+            event_loop.exit();
+            return;
+        }
+        let responses = self.terminal_state.take_responses();
+        if !responses.is_empty() {
+            self.write_to_pty(&responses);
+        }
+        self.handle_clipboard_requests();
+        self.update_window_title();
+        self.refresh_search();
+
+        // Sleep until an event or PTY output arrives, the blink phase flips, or a throttled
+        // title or search is due
+        let now = Instant::now();
+        if let Some(window) = &self.window
+            && self
+                .last_frame
+                .as_ref()
+                .is_some_and(|frame| frame.blink_visible != self.blink_visible(now))
+        {
             window.request_redraw();
         }
+        let blink_deadline = self.blink_active().then(|| self.next_blink(now));
+        let title_deadline = (self.desired_title() != self.window_title)
+            .then(|| self.last_title_update + TITLE_UPDATE_INTERVAL);
+        let search_deadline = self
+            .search_stale
+            .then(|| self.last_search + SEARCH_UPDATE_INTERVAL);
+        let deadlines = [blink_deadline, title_deadline, search_deadline];
+        let control_flow = match deadlines.into_iter().flatten().min() {
+            Some(deadline) => ControlFlow::WaitUntil(deadline),
+            None => ControlFlow::Wait,
+        };
+        event_loop.set_control_flow(control_flow);
     }
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let event_loop = EventLoop::new()?;
-    event_loop.set_control_flow(ControlFlow::Poll);
+    event_loop.set_control_flow(ControlFlow::Wait);
 
-    let mut app = VulkanTerminalApp::new();
+    let mut app = VulkanTerminalApp::new(Config::load(), event_loop.create_proxy());
     event_loop.run_app(&mut app)?;
 
     Ok(())
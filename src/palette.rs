@@ -0,0 +1,169 @@
+use crate::terminal::TerminalColor;
+
+/// A color as stored in a cell. Indexed and default colors are resolved
+/// through the palette at render time, so palette changes recolor existing text.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Color {
+    DefaultForeground,
+    DefaultBackground,
+    Indexed(u8),
+    Rgb(u8, u8, u8),
+}
+
+impl Color {
+    /// Packs the color into 4 bytes for compressed scrollback.
+    pub fn to_bytes(self) -> [u8; 4] {
+        match self {
+            Self::DefaultForeground => [0, 0, 0, 0],
+            Self::DefaultBackground => [1, 0, 0, 0],
+            Self::Indexed(index) => [2, index, 0, 0],
+            Self::Rgb(r, g, b) => [3, r, g, b],
+        }
+    }
+
+    pub fn from_bytes(bytes: [u8; 4]) -> Option<Self> {
+        match bytes {
+            [0, ..] => Some(Self::DefaultForeground),
+            [1, ..] => Some(Self::DefaultBackground),
+            [2, index, ..] => Some(Self::Indexed(index)),
+            [3, r, g, b] => Some(Self::Rgb(r, g, b)),
+            _ => None,
+        }
+    }
+}
+
+/// The xterm defaults for the 16 ANSI colors.
+const ANSI_COLORS: [(u8, u8, u8); 16] = [
+    (0x00, 0x00, 0x00),
+    (0xcd, 0x00, 0x00),
+    (0x00, 0xcd, 0x00),
+    (0xcd, 0xcd, 0x00),
+    (0x00, 0x00, 0xee),
+    (0xcd, 0x00, 0xcd),
+    (0x00, 0xcd, 0xcd),
+    (0xe5, 0xe5, 0xe5),
+    (0x7f, 0x7f, 0x7f),
+    (0xff, 0x00, 0x00),
+    (0x00, 0xff, 0x00),
+    (0xff, 0xff, 0x00),
+    (0x5c, 0x5c, 0xff),
+    (0xff, 0x00, 0xff),
+    (0x00, 0xff, 0xff),
+    (0xff, 0xff, 0xff),
+];
+
+const DEFAULT_FOREGROUND: TerminalColor = TerminalColor::WHITE;
+const DEFAULT_BACKGROUND: TerminalColor = TerminalColor::BLACK;
+
+/// The colors programs can change at runtime with OSC 4/10/11/12.
+pub struct Palette {
+    pub colors: [TerminalColor; 256],
+    pub foreground: TerminalColor,
+    pub background: TerminalColor,
+    /// Cursor color; `None` draws the cursor in the foreground color.
+    pub cursor: Option<TerminalColor>,
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self {
+            colors: std::array::from_fn(|index| Self::default_indexed(index as u8)),
+            foreground: DEFAULT_FOREGROUND,
+            background: DEFAULT_BACKGROUND,
+            cursor: None,
+        }
+    }
+}
+
+impl Palette {
+    /// The standard xterm-256color table: 16 ANSI colors, a 6x6x6 cube and a 24-step gray ramp.
+    pub fn default_indexed(index: u8) -> TerminalColor {
+        let (r, g, b) = match index {
+            0..=15 => ANSI_COLORS[index as usize],
+            16..=231 => {
+                let cube = index - 16;
+                let level = |value: u8| if value == 0 { 0 } else { 55 + value * 40 };
+                (level(cube / 36), level((cube / 6) % 6), level(cube % 6))
+            }
+            232..=255 => {
+                let gray = 8 + (index - 232) * 10;
+                (gray, gray, gray)
+            }
+        };
+        TerminalColor::from_rgb8(r, g, b)
+    }
+
+    pub fn reset_indexed(&mut self, index: u8) {
+        self.colors[index as usize] = Self::default_indexed(index);
+    }
+
+    pub fn reset_foreground(&mut self) {
+        self.foreground = DEFAULT_FOREGROUND;
+    }
+
+    pub fn reset_background(&mut self) {
+        self.background = DEFAULT_BACKGROUND;
+    }
+
+    pub fn reset_cursor(&mut self) {
+        self.cursor = None;
+    }
+
+    pub fn cursor_color(&self) -> TerminalColor {
+        self.cursor.unwrap_or(self.foreground)
+    }
+
+    pub fn resolve(&self, color: Color) -> TerminalColor {
+        match color {
+            Color::DefaultForeground => self.foreground,
+            Color::DefaultBackground => self.background,
+            Color::Indexed(index) => self.colors[index as usize],
+            Color::Rgb(r, g, b) => TerminalColor::from_rgb8(r, g, b),
+        }
+    }
+}
+
+/// Parses an X11 color specification as used by OSC 4/10/11/12: `rgb:r/g/b` with 1–4 hex
+/// digits per component, or `#rgb` with 1–4 digits per component packed together.
+pub fn parse_color_spec(spec: &[u8]) -> Option<TerminalColor> {
+    let spec = std::str::from_utf8(spec).ok()?;
+
+    let components: Vec<&str> = if let Some(rgb) = spec.strip_prefix("rgb:") {
+        rgb.split('/').collect()
+    } else if let Some(hex) = spec.strip_prefix('#') {
+        let valid = hex.bytes().all(|byte| byte.is_ascii_hexdigit());
+        if !valid || hex.is_empty() || hex.len() % 3 != 0 || hex.len() > 12 {
+            return None;
+        }
+        let digits = hex.len() / 3;
+        (0..3).map(|i| &hex[i * digits..(i + 1) * digits]).collect()
+    } else {
+        return None;
+    };
+
+    let [r, g, b] = components.as_slice() else {
+        return None;
+    };
+    let scale = |component: &str| -> Option<f32> {
+        let valid = component.bytes().all(|byte| byte.is_ascii_hexdigit());
+        if !valid || component.is_empty() || component.len() > 4 {
+            return None;
+        }
+        let value = u16::from_str_radix(component, 16).ok()?;
+        let max = (1u32 << (4 * component.len())) - 1;
+        Some(value as f32 / max as f32)
+    };
+
+    Some(TerminalColor::new(scale(r)?, scale(g)?, scale(b)?, 1.0))
+}
+
+/// Formats a color the way xterm answers color queries: `rgb:rrrr/gggg/bbbb`.
+pub fn format_color_spec(color: TerminalColor) -> String {
+    let component = |value: f32| (value.clamp(0.0, 1.0) * 65535.0).round() as u16;
+    format!(
+        "rgb:{:04x}/{:04x}/{:04x}",
+        component(color.r),
+        component(color.g),
+        component(color.b)
+    )
+}
@@ -0,0 +1,489 @@
+/// Escape sequence parser following Paul Williams' DEC VT500 state machine
+/// (https://vt100.net/emu/dec_ansi_parser), extended with colon sub-parameters
+/// and UTF-8 decoding in the ground state.
+const MAX_PARAMS: usize = 32;
+const MAX_INTERMEDIATES: usize = 2;
+const MAX_OSC_PARAMS: usize = 16;
+/// Default for the most bytes of an OSC string kept; the rest is dropped.
+pub const MAX_OSC_LENGTH: usize = 1 << 20;
+
+pub trait Perform {
+    /// A printable character in the ground state.
+    fn print(&mut self, ch: char);
+
+    /// A C0 control byte.
+    fn execute(&mut self, byte: u8);
+
+    fn csi_dispatch(&mut self, params: &Params, intermediates: &[u8], ignore: bool, action: char);
+
+    fn esc_dispatch(&mut self, intermediates: &[u8], ignore: bool, byte: u8);
+
+    /// A complete OSC string split on `;`. `bell_terminated` records whether it ended with BEL
+    /// rather than ST, so replies can use the same terminator. `truncated` is set when the
+    /// string was longer than the parser keeps and its end is missing.
+    fn osc_dispatch(&mut self, params: &[&[u8]], bell_terminated: bool, truncated: bool);
+
+    /// Start of a DCS string; the data follows through `put` and ends with `unhook`.
+    fn hook(&mut self, params: &Params, intermediates: &[u8], ignore: bool, action: char);
+
+    fn put(&mut self, byte: u8);
+
+    fn unhook(&mut self);
+}
+
+/// CSI/DCS numeric parameters. Each parameter is a group of one value plus any
+/// colon-separated sub-parameters, e.g. `38:2::255:0:0` is a single group.
+#[derive(Default)]
+pub struct Params {
+    values: [u16; MAX_PARAMS],
+    group_lengths: [u8; MAX_PARAMS],
+    len: usize,
+    groups: usize,
+    current_group_length: u8,
+}
+
+impl Params {
+    pub fn iter(&self) -> ParamsIter<'_> {
+        ParamsIter {
+            params: self,
+            value_index: 0,
+            group_index: 0,
+        }
+    }
+
+    /// The first value of the group at `index`, or `None` when absent.
+    pub fn get(&self, index: usize) -> Option<u16> {
+        self.iter().nth(index).map(|group| group[0])
+    }
+
+    fn clear(&mut self) {
+        self.len = 0;
+        self.groups = 0;
+        self.current_group_length = 0;
+    }
+
+    fn is_full(&self) -> bool {
+        self.len >= MAX_PARAMS
+    }
+
+    fn push(&mut self, value: u16) {
+        self.finish_group();
+        self.values[self.len] = value;
+        self.len += 1;
+        self.current_group_length = 1;
+    }
+
+    fn push_subparam(&mut self, value: u16) {
+        self.values[self.len] = value;
+        self.len += 1;
+        self.current_group_length += 1;
+    }
+
+    fn finish_group(&mut self) {
+        if self.current_group_length > 0 {
+            self.group_lengths[self.groups] = self.current_group_length;
+            self.groups += 1;
+            self.current_group_length = 0;
+        }
+    }
+}
+
+pub struct ParamsIter<'a> {
+    params: &'a Params,
+    value_index: usize,
+    group_index: usize,
+}
+
+impl<'a> Iterator for ParamsIter<'a> {
+    type Item = &'a [u16];
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.group_index >= self.params.groups {
+            return None;
+        }
+
+        let length = self.params.group_lengths[self.group_index] as usize;
+        let group = &self.params.values[self.value_index..self.value_index + length];
+        self.value_index += length;
+        self.group_index += 1;
+        Some(group)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    Ground,
+    Escape,
+    EscapeIntermediate,
+    CsiEntry,
+    CsiParam,
+    CsiIntermediate,
+    CsiIgnore,
+    DcsEntry,
+    DcsParam,
+    DcsIntermediate,
+    DcsPassthrough,
+    DcsIgnore,
+    OscString,
+    SosPmApcString,
+}
+
+pub struct Parser {
+    state: State,
+    params: Params,
+    param: u16,
+    param_started: bool,
+    subparam: bool,
+    intermediates: [u8; MAX_INTERMEDIATES],
+    intermediate_count: usize,
+    ignoring: bool,
+    osc_raw: Vec<u8>,
+    osc_param_ends: Vec<usize>,
+    /// Set once bytes of the current OSC string were dropped past `max_osc_length`.
+    osc_truncated: bool,
+    max_osc_length: usize,
+    utf8_buffer: [u8; 4],
+    utf8_length: usize,
+    utf8_needed: usize,
+}
+
+impl Default for Parser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Parser {
+    pub fn new() -> Self {
+        Self {
+            state: State::Ground,
+            params: Params::default(),
+            param: 0,
+            param_started: false,
+            subparam: false,
+            intermediates: [0; MAX_INTERMEDIATES],
+            intermediate_count: 0,
+            ignoring: false,
+            osc_raw: Vec::new(),
+            osc_param_ends: Vec::new(),
+            osc_truncated: false,
+            max_osc_length: MAX_OSC_LENGTH,
+            utf8_buffer: [0; 4],
+            utf8_length: 0,
+            utf8_needed: 0,
+        }
+    }
+
+    /// Sets how many bytes of an OSC string are kept, not counting the `;` separators.
+    pub fn set_max_osc_length(&mut self, length: usize) {
+        self.max_osc_length = length;
+    }
+
+    pub fn advance<P: Perform>(&mut self, performer: &mut P, bytes: &[u8]) {
+        for &byte in bytes {
+            self.advance_byte(performer, byte);
+        }
+    }
+
+    fn advance_byte<P: Perform>(&mut self, performer: &mut P, byte: u8) {
+        if self.state == State::Ground && (self.utf8_needed > 0 || byte >= 0x80) {
+            self.advance_utf8(performer, byte);
+            return;
+        }
+
+        // Transitions that apply from any state
+        match byte {
+            0x18 | 0x1a => {
+                self.leave_string(performer, true);
+                performer.execute(byte);
+                self.state = State::Ground;
+                return;
+            }
+            0x1b => {
+                self.leave_string(performer, false);
+                self.enter_escape();
+                return;
+            }
+            _ => {}
+        }
+
+        match self.state {
+            State::Ground => match byte {
+                0x00..=0x1f => performer.execute(byte),
+                0x20..=0x7e => performer.print(byte as char),
+                _ => {}
+            },
+            State::Escape => match byte {
+                0x00..=0x1f => performer.execute(byte),
+                0x20..=0x2f => {
+                    self.collect(byte);
+                    self.state = State::EscapeIntermediate;
+                }
+                b'[' => self.enter_csi(),
+                b']' => self.enter_osc(),
+                b'P' => self.enter_dcs(),
+                b'X' | b'^' | b'_' => self.state = State::SosPmApcString,
+                0x30..=0x7e => {
+                    performer.esc_dispatch(self.intermediates(), self.ignoring, byte);
+                    self.state = State::Ground;
+                }
+                _ => {}
+            },
+            State::EscapeIntermediate => match byte {
+                0x00..=0x1f => performer.execute(byte),
+                0x20..=0x2f => self.collect(byte),
+                0x30..=0x7e => {
+                    performer.esc_dispatch(self.intermediates(), self.ignoring, byte);
+                    self.state = State::Ground;
+                }
+                _ => {}
+            },
+            State::CsiEntry | State::CsiParam => match byte {
+                0x00..=0x1f => performer.execute(byte),
+                b'0'..=b'9' | b';' | b':' => {
+                    self.param_byte(byte);
+                    self.state = State::CsiParam;
+                }
+                0x3c..=0x3f => {
+                    if self.state == State::CsiEntry {
+                        self.collect(byte);
+                        self.state = State::CsiParam;
+                    } else {
+                        self.state = State::CsiIgnore;
+                    }
+                }
+                0x20..=0x2f => {
+                    self.collect(byte);
+                    self.state = State::CsiIntermediate;
+                }
+                0x40..=0x7e => self.csi_dispatch(performer, byte),
+                _ => {}
+            },
+            State::CsiIntermediate => match byte {
+                0x00..=0x1f => performer.execute(byte),
+                0x20..=0x2f => self.collect(byte),
+                0x30..=0x3f => self.state = State::CsiIgnore,
+                0x40..=0x7e => self.csi_dispatch(performer, byte),
+                _ => {}
+            },
+            State::CsiIgnore => match byte {
+                0x00..=0x1f => performer.execute(byte),
+                0x40..=0x7e => self.state = State::Ground,
+                _ => {}
+            },
+            State::DcsEntry | State::DcsParam => match byte {
+                b'0'..=b'9' | b';' | b':' => {
+                    self.param_byte(byte);
+                    self.state = State::DcsParam;
+                }
+                0x3c..=0x3f => {
+                    if self.state == State::DcsEntry {
+                        self.collect(byte);
+                        self.state = State::DcsParam;
+                    } else {
+                        self.state = State::DcsIgnore;
+                    }
+                }
+                0x20..=0x2f => {
+                    self.collect(byte);
+                    self.state = State::DcsIntermediate;
+                }
+                0x40..=0x7e => self.hook(performer, byte),
+                _ => {}
+            },
+            State::DcsIntermediate => match byte {
+                0x20..=0x2f => self.collect(byte),
+                0x30..=0x3f => self.state = State::DcsIgnore,
+                0x40..=0x7e => self.hook(performer, byte),
+                _ => {}
+            },
+            State::DcsPassthrough => match byte {
+                0x7f => {}
+                _ => performer.put(byte),
+            },
+            State::DcsIgnore | State::SosPmApcString => {}
+            State::OscString => match byte {
+                0x07 => {
+                    self.osc_dispatch(performer, true);
+                    self.state = State::Ground;
+                }
+                0x00..=0x1f => {}
+                _ => self.osc_put(byte),
+            },
+        }
+    }
+
+    fn advance_utf8<P: Perform>(&mut self, performer: &mut P, byte: u8) {
+        if self.utf8_needed == 0 {
+            let needed = match byte {
+                0xc2..=0xdf => 1,
+                0xe0..=0xef => 2,
+                0xf0..=0xf4 => 3,
+                _ => {
+                    performer.print(char::REPLACEMENT_CHARACTER);
+                    return;
+                }
+            };
+            self.utf8_buffer[0] = byte;
+            self.utf8_length = 1;
+            self.utf8_needed = needed;
+            return;
+        }
+
+        if byte & 0xc0 != 0x80 {
+            // Truncated sequence: report it, then reprocess this byte on its own
+            self.utf8_needed = 0;
+            performer.print(char::REPLACEMENT_CHARACTER);
+            self.advance_byte(performer, byte);
+            return;
+        }
+
+        self.utf8_buffer[self.utf8_length] = byte;
+        self.utf8_length += 1;
+        self.utf8_needed -= 1;
+
+        if self.utf8_needed == 0 {
+            let ch = std::str::from_utf8(&self.utf8_buffer[..self.utf8_length])
+                .ok()
+                .and_then(|s| s.chars().next())
+                .unwrap_or(char::REPLACEMENT_CHARACTER);
+            performer.print(ch);
+        }
+    }
+
+    /// Ends any OSC or DCS string in progress. CAN and SUB abort an OSC instead of completing it.
+    fn leave_string<P: Perform>(&mut self, performer: &mut P, aborted: bool) {
+        match self.state {
+            State::OscString if !aborted => self.osc_dispatch(performer, false),
+            State::DcsPassthrough => performer.unhook(),
+            _ => {}
+        }
+    }
+
+    fn clear(&mut self) {
+        self.params.clear();
+        self.param = 0;
+        self.param_started = false;
+        self.subparam = false;
+        self.intermediate_count = 0;
+        self.ignoring = false;
+    }
+
+    fn enter_escape(&mut self) {
+        self.clear();
+        self.state = State::Escape;
+    }
+
+    fn enter_csi(&mut self) {
+        self.clear();
+        self.state = State::CsiEntry;
+    }
+
+    fn enter_dcs(&mut self) {
+        self.clear();
+        self.state = State::DcsEntry;
+    }
+
+    fn enter_osc(&mut self) {
+        self.osc_raw.clear();
+        self.osc_param_ends.clear();
+        self.osc_truncated = false;
+        self.state = State::OscString;
+    }
+
+    fn intermediates(&self) -> &[u8] {
+        &self.intermediates[..self.intermediate_count]
+    }
+
+    fn collect(&mut self, byte: u8) {
+        if self.intermediate_count == MAX_INTERMEDIATES {
+            self.ignoring = true;
+        } else {
+            self.intermediates[self.intermediate_count] = byte;
+            self.intermediate_count += 1;
+        }
+    }
+
+    fn param_byte(&mut self, byte: u8) {
+        match byte {
+            b';' | b':' => {
+                self.finish_param();
+                self.subparam = byte == b':';
+                // A trailing separator still implies a (default) parameter after it
+                self.param_started = true;
+            }
+            _ => {
+                self.param = self
+                    .param
+                    .saturating_mul(10)
+                    .saturating_add((byte - b'0') as u16);
+                self.param_started = true;
+            }
+        }
+    }
+
+    fn finish_param(&mut self) {
+        if self.params.is_full() {
+            self.ignoring = true;
+        } else if self.subparam {
+            self.params.push_subparam(self.param);
+        } else {
+            self.params.push(self.param);
+        }
+        self.param = 0;
+        self.param_started = false;
+    }
+
+    fn finish_params(&mut self) {
+        if self.param_started {
+            self.finish_param();
+        }
+        self.params.finish_group();
+    }
+
+    fn csi_dispatch<P: Perform>(&mut self, performer: &mut P, byte: u8) {
+        self.finish_params();
+        performer.csi_dispatch(
+            &self.params,
+            &self.intermediates[..self.intermediate_count],
+            self.ignoring,
+            byte as char,
+        );
+        self.state = State::Ground;
+    }
+
+    fn hook<P: Perform>(&mut self, performer: &mut P, byte: u8) {
+        self.finish_params();
+        performer.hook(
+            &self.params,
+            &self.intermediates[..self.intermediate_count],
+            self.ignoring,
+            byte as char,
+        );
+        self.state = State::DcsPassthrough;
+    }
+
+    fn osc_put(&mut self, byte: u8) {
+        if self.osc_raw.len() >= self.max_osc_length {
+            self.osc_truncated = true;
+            return;
+        }
+        if byte == b';' && self.osc_param_ends.len() < MAX_OSC_PARAMS - 1 {
+            self.osc_param_ends.push(self.osc_raw.len());
+        } else {
+            self.osc_raw.push(byte);
+        }
+    }
+
+    fn osc_dispatch<P: Perform>(&mut self, performer: &mut P, bell_terminated: bool) {
+        let mut params: Vec<&[u8]> = Vec::with_capacity(self.osc_param_ends.len() + 1);
+        let mut start = 0;
+        for &end in &self.osc_param_ends {
+            params.push(&self.osc_raw[start..end]);
+            start = end;
+        }
+        params.push(&self.osc_raw[start..]);
+
+        performer.osc_dispatch(&params, bell_terminated, self.osc_truncated);
+    }
+}
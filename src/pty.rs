@@ -0,0 +1,155 @@
+use crate::terminfo;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command, Stdio};
+use std::ptr;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+
+/// Output chunks the reader thread can queue before it waits for the app to catch up.
+const CHANNEL_CHUNKS: usize = 64;
+
+pub enum PtyEvent {
+    Output(Vec<u8>),
+    Exited,
+}
+
+pub struct Pty {
+    master: File,
+    child: Child,
+    events: Receiver<PtyEvent>,
+}
+
+impl Pty {
+    pub fn spawn(
+        program: &str,
+        args: &[String],
+        columns: u16,
+        rows: u16,
+        wake: impl Fn() + Send + 'static,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut master_fd = -1;
+        let mut slave_fd = -1;
+        let window_size = Self::window_size(columns, rows);
+
+        let result = unsafe {
+            libc::openpty(
+                &mut master_fd,
+                &mut slave_fd,
+                ptr::null_mut(),
+                ptr::null(),
+                &window_size,
+            )
+        };
+        if result != 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+
+        let master = unsafe { OwnedFd::from_raw_fd(master_fd) };
+        let slave = unsafe { OwnedFd::from_raw_fd(slave_fd) };
+
+        // The child must not inherit the master side, otherwise it never sees EOF/HUP
+        unsafe {
+            libc::fcntl(master.as_raw_fd(), libc::F_SETFD, libc::FD_CLOEXEC);
+        }
+
+        let mut command = Command::new(program);
+        command
+            .args(args)
+            .env("TERM", terminfo::TERM_NAME)
+            .env("COLORTERM", "truecolor")
+            .stdin(Stdio::from(slave.try_clone()?))
+            .stdout(Stdio::from(slave.try_clone()?))
+            .stderr(Stdio::from(slave));
+
+        unsafe {
+            command.pre_exec(|| {
+                // Become a session leader and take the PTY as the controlling terminal
+                if libc::setsid() == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+                if libc::ioctl(0, libc::TIOCSCTTY as _, 0) == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+
+        let child = command.spawn()?;
+
+        let master = File::from(master);
+        let mut reader = master.try_clone()?;
+        let (sender, events) = mpsc::sync_channel(CHANNEL_CHUNKS);
+
+        thread::spawn(move || {
+            let mut buffer = [0u8; 4096];
+            loop {
+                match reader.read(&mut buffer) {
+                    Ok(0) => break,
+                    Ok(count) => {
+                        if sender
+                            .send(PtyEvent::Output(buffer[..count].to_vec()))
+                            .is_err()
+                        {
+                            return;
+                        }
+                        wake();
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    // Linux reports EIO on the master once the last slave fd is closed
+                    Err(_) => break,
+                }
+            }
+            let _ = sender.send(PtyEvent::Exited);
+            wake();
+        });
+
+        Ok(Self {
+            master,
+            child,
+            events,
+        })
+    }
+
+    fn window_size(columns: u16, rows: u16) -> libc::winsize {
+        libc::winsize {
+            ws_row: rows,
+            ws_col: columns,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        }
+    }
+
+    pub fn resize(&self, columns: u16, rows: u16) -> io::Result<()> {
+        let window_size = Self::window_size(columns, rows);
+        let result =
+            unsafe { libc::ioctl(self.master.as_raw_fd(), libc::TIOCSWINSZ, &window_size) };
+        if result == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    pub fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        self.master.write_all(data)
+    }
+
+    pub fn try_recv(&self) -> Option<PtyEvent> {
+        match self.events.try_recv() {
+            Ok(event) => Some(event),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => Some(PtyEvent::Exited),
+        }
+    }
+}
+
+impl Drop for Pty {
+    fn drop(&mut self) {
+        unsafe {
+            libc::kill(self.child.id() as libc::pid_t, libc::SIGHUP);
+        }
+        let _ = self.child.wait();
+    }
+}
@@ -0,0 +1,388 @@
+use crate::grid::Row;
+use crate::terminal::{ENCODED_CELL_SIZE, TerminalCell};
+use flate2::Compression;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::ops::Range;
+use std::os::unix::fs::FileExt;
+
+/// Rows per cold block. Blocks are compressed, spilled and dropped as a unit.
+pub const BLOCK_ROWS: usize = 256;
+/// Decompressed blocks kept besides the ones in view, so scrolling back and forth across
+/// a block boundary doesn't decompress the same block each time.
+const SPARE_CACHED_BLOCKS: usize = 2;
+
+/// Where a compressed block is kept.
+enum Block {
+    Memory(Vec<u8>),
+    /// A region of the spill file.
+    Disk {
+        offset: u64,
+        length: usize,
+    },
+}
+
+/// Scrollback rows too old to keep as cells, compressed in blocks of `BLOCK_ROWS` rows.
+/// Once the compressed blocks outgrow the memory budget, the oldest move to a temporary
+/// file. The file is unlinked as soon as it is created, so it goes away with the process.
+#[derive(Default)]
+pub struct ColdHistory {
+    /// Oldest first; blocks on disk always come before those in memory.
+    blocks: VecDeque<Block>,
+    /// How many of the leading blocks are on disk.
+    spilled: usize,
+    /// Rows at the start of the first block that have already been dropped.
+    skipped: usize,
+    /// Id of `blocks[0]`. Ids keep counting up as blocks are dropped, so cached blocks
+    /// stay identifiable.
+    first_id: u64,
+    /// Compressed bytes held in memory.
+    memory_used: usize,
+    max_memory: usize,
+    spill_file: Option<File>,
+    /// Set once the spill file couldn't be used, after which old blocks are dropped.
+    spill_failed: bool,
+    /// Unused regions of the spill file as (offset, length), reused by later spills.
+    free_regions: Vec<(u64, usize)>,
+    file_end: u64,
+    /// Decompressed blocks by id, least recently used first.
+    cache: Vec<(u64, Vec<Row>)>,
+}
+
+impl ColdHistory {
+    pub fn len(&self) -> usize {
+        self.blocks.len() * BLOCK_ROWS - self.skipped
+    }
+
+    /// Sets how many compressed bytes may stay in memory, spilling blocks if needed.
+    /// Returns how many of the oldest rows had to be dropped instead.
+    pub fn set_max_memory(&mut self, bytes: usize) -> usize {
+        self.max_memory = bytes;
+        self.enforce_memory_budget()
+    }
+
+    /// Compresses `rows`, `BLOCK_ROWS` of them, into a block after all the others.
+    /// Returns how many of the oldest rows were dropped to stay within the memory budget.
+    pub fn push_block(&mut self, rows: &[Row]) -> usize {
+        let data = compress(rows);
+        self.memory_used += data.len();
+        self.blocks.push_back(Block::Memory(data));
+        self.enforce_memory_budget()
+    }
+
+    /// Takes out the blocks, leaving an empty history with the same memory budget.
+    pub fn take(&mut self) -> Self {
+        let old = std::mem::take(self);
+        self.max_memory = old.max_memory;
+        self.spill_failed = old.spill_failed;
+        old
+    }
+
+    /// Drops the oldest `count` rows.
+    pub fn drop_oldest(&mut self, count: usize) {
+        self.skipped += count.min(self.len());
+        while self.skipped >= BLOCK_ROWS {
+            self.skipped -= BLOCK_ROWS;
+            self.pop_block();
+        }
+    }
+
+    pub fn clear(&mut self) {
+        while !self.blocks.is_empty() {
+            self.pop_block();
+        }
+        self.skipped = 0;
+        self.free_regions.clear();
+        self.file_end = 0;
+        if let Some(file) = &self.spill_file {
+            let _ = file.set_len(0);
+        }
+    }
+
+    fn pop_block(&mut self) {
+        match self.blocks.pop_front() {
+            Some(Block::Memory(data)) => self.memory_used -= data.len(),
+            Some(Block::Disk { offset, length }) => {
+                self.spilled -= 1;
+                self.free_region(offset, length);
+            }
+            None => return,
+        }
+        let id = self.first_id;
+        self.cache.retain(|(cached, _)| *cached != id);
+        self.first_id += 1;
+    }
+
+    /// Moves the oldest blocks in memory to the spill file until the memory budget is met.
+    /// Without a usable spill file, the oldest blocks are dropped instead; returns how
+    /// many rows that dropped.
+    fn enforce_memory_budget(&mut self) -> usize {
+        let mut dropped = 0;
+        while self.memory_used > self.max_memory && self.spilled < self.blocks.len() {
+            if self.spill_failed {
+                dropped += BLOCK_ROWS - self.skipped;
+                self.skipped = 0;
+                self.pop_block();
+                continue;
+            }
+            if let Err(e) = self.spill() {
+                eprintln!("Failed to spill scrollback to disk: {}", e);
+                self.spill_failed = true;
+            }
+        }
+        dropped
+    }
+
+    /// Writes the oldest block in memory to the spill file.
+    fn spill(&mut self) -> io::Result<()> {
+        if self.spill_file.is_none() {
+            self.spill_file = Some(tempfile::tempfile()?);
+        }
+        let (Some(file), Block::Memory(data)) = (&self.spill_file, &self.blocks[self.spilled])
+        else {
+            unreachable!("blocks after the spilled ones are in memory");
+        };
+        let length = data.len();
+        let offset = match self
+            .free_regions
+            .iter()
+            .position(|&(_, free)| free >= length)
+        {
+            Some(index) => self.free_regions[index].0,
+            None => self.file_end,
+        };
+        file.write_all_at(data, offset)?;
+
+        self.take_region(offset, length);
+        self.memory_used -= length;
+        self.blocks[self.spilled] = Block::Disk { offset, length };
+        self.spilled += 1;
+        Ok(())
+    }
+
+    /// Marks `length` bytes at `offset` as used: the start of a free region, or the end
+    /// of the file.
+    fn take_region(&mut self, offset: u64, length: usize) {
+        let Some(index) = self.free_regions.iter().position(|&(at, _)| at == offset) else {
+            self.file_end = offset + length as u64;
+            return;
+        };
+        let (_, free) = self.free_regions[index];
+        if free == length {
+            self.free_regions.remove(index);
+        } else {
+            self.free_regions[index] = (offset + length as u64, free - length);
+        }
+    }
+
+    /// Returns a region to the free list, merging it with neighbouring free regions.
+    fn free_region(&mut self, mut offset: u64, mut length: usize) {
+        if let Some(index) = self
+            .free_regions
+            .iter()
+            .position(|&(at, free)| at + free as u64 == offset)
+        {
+            let (at, free) = self.free_regions.remove(index);
+            offset = at;
+            length += free;
+        }
+        let end = offset + length as u64;
+        if let Some(index) = self.free_regions.iter().position(|&(at, _)| at == end) {
+            let (_, free) = self.free_regions.remove(index);
+            length += free;
+        }
+        self.free_regions.push((offset, length));
+    }
+
+    /// Decompresses the blocks holding `rows` into the cache for `cached_row`, evicting
+    /// the least recently used others.
+    pub fn load(&mut self, rows: Range<usize>, width: usize) {
+        if rows.is_empty() {
+            return;
+        }
+        let first = (rows.start + self.skipped) / BLOCK_ROWS;
+        let last = (rows.end - 1 + self.skipped) / BLOCK_ROWS;
+        for index in first..=last {
+            let id = self.first_id + index as u64;
+            if let Some(position) = self.cache.iter().position(|(cached, _)| *cached == id) {
+                let entry = self.cache.remove(position);
+                self.cache.push(entry);
+                continue;
+            }
+            match self.read_block(index, Some(width)) {
+                Ok(rows) => self.cache.push((id, rows)),
+                Err(e) => eprintln!("Failed to read scrollback: {}", e),
+            }
+        }
+        let keep = last - first + 1 + SPARE_CACHED_BLOCKS;
+        if self.cache.len() > keep {
+            self.cache.drain(..self.cache.len() - keep);
+        }
+    }
+
+    /// Forgets decompressed blocks, e.g. because they were decoded for another width.
+    pub fn clear_cache(&mut self) {
+        self.cache.clear();
+    }
+
+    /// Row `index`, counted from the oldest, if `load` has decompressed it.
+    pub fn cached_row(&self, index: usize) -> Option<&Row> {
+        let position = index + self.skipped;
+        let id = self.first_id + (position / BLOCK_ROWS) as u64;
+        let (_, rows) = self.cache.iter().find(|(cached, _)| *cached == id)?;
+        rows.get(position % BLOCK_ROWS)
+    }
+
+    /// Calls `f` with every row from row `start`, counted from the oldest. Rows not in the
+    /// cache are passed as stored, without the blanks ending a line that doesn't wrap.
+    /// Rows of blocks that can't be read are passed as `blank`.
+    pub fn for_each_row_from(&self, start: usize, blank: &Row, f: &mut impl FnMut(&Row)) {
+        let start = start + self.skipped;
+        for index in start / BLOCK_ROWS..self.blocks.len() {
+            let skip = if index == start / BLOCK_ROWS {
+                start % BLOCK_ROWS
+            } else {
+                0
+            };
+            let id = self.first_id + index as u64;
+            let decoded;
+            let rows = match self.cache.iter().find(|(cached, _)| *cached == id) {
+                Some((_, rows)) => rows,
+                None => match self.read_block(index, None) {
+                    Ok(rows) => {
+                        decoded = rows;
+                        &decoded
+                    }
+                    Err(e) => {
+                        eprintln!("Failed to read scrollback: {}", e);
+                        (skip..BLOCK_ROWS).for_each(|_| f(blank));
+                        continue;
+                    }
+                },
+            };
+            rows.iter().skip(skip).for_each(&mut *f);
+        }
+    }
+
+    /// Decompresses block `index`, cropping or padding its rows to `width` if given.
+    fn read_block(&self, index: usize, width: Option<usize>) -> io::Result<Vec<Row>> {
+        let data = match &self.blocks[index] {
+            Block::Memory(data) => Cow::Borrowed(data.as_slice()),
+            Block::Disk { offset, length } => {
+                let file = self.spill_file.as_ref().ok_or(io::ErrorKind::NotFound)?;
+                let mut data = vec![0; *length];
+                file.read_exact_at(&mut data, *offset)?;
+                Cow::Owned(data)
+            }
+        };
+        decompress(&data, width)
+    }
+}
+
+/// Encodes rows as runs of cells that differ only in their character: one encoded cell
+/// and the run's characters as UTF-8. Blank cells at the end of a line that doesn't wrap
+/// are left out. The result is deflated.
+fn compress(rows: &[Row]) -> Vec<u8> {
+    let mut raw = Vec::new();
+    for row in rows {
+        let length = match row.wrapped {
+            true => row.len(),
+            false => row
+                .iter()
+                .rposition(|cell| *cell != TerminalCell::default())
+                .map_or(0, |index| index + 1),
+        };
+        raw.push(u8::from(row.wrapped));
+        raw.extend((length as u32).to_le_bytes());
+
+        let mut cells = &row[..length];
+        while let Some(first) = cells.first() {
+            let template = TerminalCell {
+                character: ' ',
+                ..*first
+            };
+            let run = cells
+                .iter()
+                .take_while(|cell| {
+                    TerminalCell {
+                        character: ' ',
+                        ..**cell
+                    } == template
+                })
+                .count();
+            template.encode(&mut raw);
+            raw.extend((run as u32).to_le_bytes());
+            let mut buffer = [0; 4];
+            for cell in &cells[..run] {
+                raw.extend(cell.character.encode_utf8(&mut buffer).as_bytes());
+            }
+            cells = &cells[run..];
+        }
+    }
+
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::fast());
+    encoder
+        .write_all(&raw)
+        .and_then(|_| encoder.finish())
+        .expect("writing to a Vec can't fail")
+}
+
+fn decompress(data: &[u8], width: Option<usize>) -> io::Result<Vec<Row>> {
+    let mut raw = Vec::new();
+    DeflateDecoder::new(data).read_to_end(&mut raw)?;
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "corrupt scrollback block");
+
+    let mut bytes = raw.as_slice();
+    let mut rows = Vec::with_capacity(BLOCK_ROWS);
+    while let Some((&wrapped, rest)) = bytes.split_first() {
+        let (length, rest) = rest.split_first_chunk::<4>().ok_or_else(invalid)?;
+        let length = u32::from_le_bytes(*length) as usize;
+        bytes = rest;
+
+        let mut cells = Vec::with_capacity(width.unwrap_or(length));
+        while cells.len() < length {
+            let (template, rest) = bytes
+                .split_first_chunk::<ENCODED_CELL_SIZE>()
+                .ok_or_else(invalid)?;
+            let template = TerminalCell::decode(template).ok_or_else(invalid)?;
+            let (run, rest) = rest.split_first_chunk::<4>().ok_or_else(invalid)?;
+            let run = u32::from_le_bytes(*run) as usize;
+            bytes = rest;
+
+            for _ in 0..run {
+                let (character, rest) = split_char(bytes).ok_or_else(invalid)?;
+                bytes = rest;
+                cells.push(TerminalCell {
+                    character,
+                    ..template
+                });
+            }
+        }
+
+        if let Some(width) = width {
+            cells.resize(width, TerminalCell::default());
+        }
+        rows.push(Row {
+            cells,
+            wrapped: wrapped != 0,
+        });
+    }
+    Ok(rows)
+}
+
+/// Splits the first UTF-8 encoded character off `bytes`.
+fn split_char(bytes: &[u8]) -> Option<(char, &[u8])> {
+    let length = match *bytes.first()? {
+        byte @ ..0x80 => return Some((byte as char, &bytes[1..])),
+        0xf0.. => 4,
+        0xe0.. => 3,
+        0x80.. => 2,
+    };
+    let (encoded, rest) = bytes.split_at_checked(length)?;
+    let character = std::str::from_utf8(encoded).ok()?.chars().next()?;
+    Some((character, rest))
+}
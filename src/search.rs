@@ -0,0 +1,298 @@
+use crate::terminal::{CellWidth, TerminalState};
+use regex::{Regex, RegexBuilder};
+use std::ops::Range;
+
+/// How queries are matched. These persist between searches.
+#[derive(Clone, Copy)]
+pub struct SearchOptions {
+    /// Treat the query as a regular expression rather than literal text.
+    pub regex: bool,
+    pub case_sensitive: bool,
+    /// Without `case_sensitive`, match case anyway once the query has an uppercase letter.
+    pub smart_case: bool,
+}
+
+impl Default for SearchOptions {
+    fn default() -> Self {
+        Self {
+            regex: false,
+            case_sensitive: false,
+            smart_case: true,
+        }
+    }
+}
+
+/// A match, from its first cell to the end of its last cell. Rows are ids as given by
+/// `Grid::first_row_id`, so matches stay put while old history is dropped.
+#[derive(Clone, Copy, PartialEq)]
+pub struct Match {
+    pub start: (u64, usize),
+    pub end: (u64, usize),
+}
+
+/// Columns of a viewport row to highlight.
+#[derive(Clone, PartialEq)]
+pub struct Highlight {
+    pub y: usize,
+    pub columns: Range<usize>,
+    pub current: bool,
+}
+
+/// An open search through the scrollback and the screen.
+pub struct Search {
+    pub query: String,
+    pub options: SearchOptions,
+    /// Oldest first.
+    pub matches: Vec<Match>,
+    /// Index into `matches` of the selected match.
+    pub current: Option<usize>,
+    /// Set when the query isn't a valid regular expression.
+    pub invalid: bool,
+    regex: Option<Regex>,
+    /// Rows before this id are compressed history ending a line, so their matches in
+    /// `matches` are final and `refresh` doesn't scan them again.
+    settled: u64,
+    /// Whether the last scan was of the alternate screen, whose rows have their own ids.
+    alternate: bool,
+}
+
+/// A cell's place in the text of a line.
+struct CellSpan {
+    offset: usize,
+    row: u64,
+    x: usize,
+    columns: usize,
+}
+
+impl Search {
+    pub fn new(options: SearchOptions) -> Self {
+        Self {
+            query: String::new(),
+            options,
+            matches: Vec::new(),
+            current: None,
+            invalid: false,
+            regex: None,
+            settled: 0,
+            alternate: false,
+        }
+    }
+
+    /// Whether case matters for the current query.
+    fn case_sensitive(&self) -> bool {
+        self.options.case_sensitive
+            || (self.options.smart_case && has_uppercase(&self.query, self.options.regex))
+    }
+
+    fn compile(&self) -> Result<Regex, regex::Error> {
+        let pattern = if self.options.regex {
+            self.query.clone()
+        } else {
+            regex::escape(&self.query)
+        };
+        RegexBuilder::new(&pattern)
+            .case_insensitive(!self.case_sensitive())
+            .build()
+    }
+
+    /// Finds every match of the query in the terminal's active grid, after the query or
+    /// options changed. Text continues across soft-wrapped rows.
+    pub fn run(&mut self, terminal: &TerminalState) {
+        self.matches.clear();
+        self.current = None;
+        self.settled = 0;
+        self.invalid = false;
+        self.regex = None;
+        if !self.query.is_empty() {
+            match self.compile() {
+                Ok(regex) => self.regex = Some(regex),
+                Err(_) => self.invalid = true,
+            }
+        }
+        self.refresh(terminal);
+    }
+
+    /// Updates the matches after the terminal changed, keeping the selected match if it
+    /// is still there. Only rows the last scan didn't settle are scanned again.
+    pub fn refresh(&mut self, terminal: &TerminalState) {
+        let selected = self.current.map(|index| self.matches[index]);
+        self.current = None;
+        let Some(regex) = &self.regex else {
+            return;
+        };
+        if terminal.alternate_screen_active != self.alternate {
+            self.alternate = terminal.alternate_screen_active;
+            self.settled = 0;
+        }
+
+        // Keep the matches of settled rows that are still in the history
+        let grid = &terminal.grid;
+        let first = grid.first_row_id();
+        let start = self.settled.max(first);
+        self.matches
+            .truncate(self.matches.partition_point(|m| m.start.0 < self.settled));
+        let dropped = self.matches.partition_point(|m| m.start.0 < first);
+        self.matches.drain(..dropped);
+
+        let cold_end = first + grid.cold_len() as u64;
+        let mut text = String::new();
+        let mut spans = Vec::new();
+        let mut row_id = start;
+        grid.for_each_row_from((start - first) as usize, |row| {
+            // Blanks ending a line that doesn't wrap aren't text
+            let length = match row.wrapped {
+                true => row.len(),
+                false => row
+                    .iter()
+                    .rposition(|cell| cell.character != ' ' || cell.grapheme != 0)
+                    .map_or(0, |x| x + 1),
+            };
+            for (x, cell) in row[..length].iter().enumerate() {
+                let columns = match cell.width {
+                    CellWidth::Single => 1,
+                    CellWidth::Wide => 2,
+                    CellWidth::WideSpacer | CellWidth::LeadingWideSpacer => continue,
+                };
+                spans.push(CellSpan {
+                    offset: text.len(),
+                    row: row_id,
+                    x,
+                    columns,
+                });
+                match cell.grapheme {
+                    0 => text.push(cell.character),
+                    _ => text.push_str(&terminal.cell_text(cell)),
+                }
+            }
+            row_id += 1;
+            if !row.wrapped {
+                find_in_line(regex, &mut text, &mut spans, &mut self.matches);
+                if row_id <= cold_end {
+                    self.settled = row_id;
+                }
+            }
+        });
+        find_in_line(regex, &mut text, &mut spans, &mut self.matches);
+
+        if let Some(selected) = selected {
+            let at_or_before = self.matches.partition_point(|m| m.start <= selected.start);
+            self.current = at_or_before.checked_sub(1).or(self.first_index());
+        }
+    }
+
+    fn first_index(&self) -> Option<usize> {
+        (!self.matches.is_empty()).then_some(0)
+    }
+
+    /// Selects the last match starting on or above row `row`, or the first match when
+    /// there is none, and returns it.
+    pub fn select_from(&mut self, row: u64) -> Option<Match> {
+        let above = self.matches.partition_point(|m| m.start.0 <= row);
+        self.current = above.checked_sub(1).or(self.first_index());
+        self.current.map(|index| self.matches[index])
+    }
+
+    /// Selects the next older match, or the next newer one, wrapping around at the ends,
+    /// and returns it.
+    pub fn select_next(&mut self, older: bool) -> Option<Match> {
+        let count = self.matches.len();
+        if count == 0 {
+            return None;
+        }
+        let index = match self.current {
+            Some(index) if older => (index + count - 1) % count,
+            Some(index) => (index + 1) % count,
+            None if older => count - 1,
+            None => 0,
+        };
+        self.current = Some(index);
+        Some(self.matches[index])
+    }
+
+    /// The parts of matches in the `height` rows from row `top`, `width` columns wide.
+    pub fn highlights(&self, top: u64, height: usize, width: usize) -> Vec<Highlight> {
+        let bottom = top + height as u64;
+        let first = self.matches.partition_point(|m| m.end.0 < top);
+        let mut highlights = Vec::new();
+        for (index, m) in self.matches.iter().enumerate().skip(first) {
+            if m.start.0 >= bottom {
+                break;
+            }
+            for row in m.start.0.max(top)..=m.end.0.min(bottom - 1) {
+                let start = if row == m.start.0 { m.start.1 } else { 0 };
+                let end = if row == m.end.0 { m.end.1 } else { width };
+                highlights.push(Highlight {
+                    y: (row - top) as usize,
+                    columns: start..end.min(width),
+                    current: self.current == Some(index),
+                });
+            }
+        }
+        highlights
+    }
+
+    /// The line shown in the search bar: the query, the modes and the match count.
+    pub fn status(&self) -> String {
+        let kind = if self.options.regex { "regex" } else { "text" };
+        let case = if self.options.case_sensitive {
+            "match case"
+        } else if self.options.smart_case {
+            "smart case"
+        } else {
+            "ignore case"
+        };
+        let result = if self.invalid {
+            "invalid regex".to_string()
+        } else if self.query.is_empty() {
+            String::new()
+        } else if self.matches.is_empty() {
+            "no matches".to_string()
+        } else {
+            let position = self.current.map_or(0, |index| self.matches.len() - index);
+            format!("{}/{}", position, self.matches.len())
+        };
+        format!("Find ({}, {}): {}_  {}", kind, case, self.query, result)
+    }
+}
+
+/// Adds the matches in the line `text`, whose cells are `spans`, and empties both.
+/// Trailing blanks aren't text, so `$` matches after the last character.
+fn find_in_line(
+    regex: &Regex,
+    text: &mut String,
+    spans: &mut Vec<CellSpan>,
+    matches: &mut Vec<Match>,
+) {
+    let trimmed = text.trim_end_matches(' ').len();
+    for found in regex.find_iter(&text[..trimmed]) {
+        if found.is_empty() {
+            continue;
+        }
+        let first = &spans[spans.partition_point(|span| span.offset <= found.start()) - 1];
+        let last = &spans[spans.partition_point(|span| span.offset < found.end()) - 1];
+        matches.push(Match {
+            start: (first.row, first.x),
+            end: (last.row, last.x + last.columns),
+        });
+    }
+    text.clear();
+    spans.clear();
+}
+
+/// Whether `query` has an uppercase letter. In a regular expression, the letter after a
+/// backslash is part of an escape like `\S` and doesn't count.
+fn has_uppercase(query: &str, regex: bool) -> bool {
+    let mut escaped = false;
+    for ch in query.chars() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        if regex && ch == '\\' {
+            escaped = true;
+        } else if ch.is_uppercase() {
+            return true;
+        }
+    }
+    false
+}
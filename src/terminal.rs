@@ -1,4 +1,37 @@
-use std::collections::VecDeque;
+use crate::charset::{Charset, Charsets};
+use crate::clipboard::ClipboardTarget;
+use crate::grid::{Grid, Row};
+use crate::palette::{self, Color, Palette};
+use crate::parser::{MAX_OSC_LENGTH, Params, ParamsIter, Parser, Perform};
+use crate::scrollback::BLOCK_ROWS;
+use crate::terminfo;
+use base64::Engine;
+use base64::engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::ops::Deref;
+use unicode_width::UnicodeWidthChar;
+
+/// OSC 52 payloads are standard base64; some programs leave out the padding.
+const OSC52_BASE64: GeneralPurpose = GeneralPurpose::new(
+    &base64::alphabet::STANDARD,
+    GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+
+/// xterm's limit on `CSI 22 t` pushes.
+const MAX_TITLE_STACK: usize = 10;
+/// Titles longer than this are truncated.
+const MAX_TITLE_LENGTH: usize = 1024;
+/// Rows of scrollback kept for the primary screen.
+const DEFAULT_MAX_HISTORY: usize = 1000;
+/// Values an interner holds before unused ones are first freed.
+const MIN_INTERNER_LIMIT: usize = 4096;
+
+const ZERO_WIDTH_JOINER: char = '\u{200d}';
+/// VS15 requests text presentation, VS16 emoji presentation.
+const TEXT_PRESENTATION: char = '\u{fe0e}';
+const EMOJI_PRESENTATION: char = '\u{fe0f}';
 
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub struct TerminalColor {
@@ -10,98 +43,616 @@ pub struct TerminalColor {
 
 impl TerminalColor {
     pub const BLACK: Self = Self::new(0.0, 0.0, 0.0, 1.0);
-    pub const RED: Self = Self::new(1.0, 0.0, 0.0, 1.0);
-    pub const GREEN: Self = Self::new(0.0, 1.0, 0.0, 1.0);
-    pub const YELLOW: Self = Self::new(1.0, 1.0, 0.0, 1.0);
-    pub const BLUE: Self = Self::new(0.0, 0.0, 1.0, 1.0);
-    pub const MAGENTA: Self = Self::new(1.0, 0.0, 1.0, 1.0);
-    pub const CYAN: Self = Self::new(0.0, 1.0, 1.0, 1.0);
     pub const WHITE: Self = Self::new(1.0, 1.0, 1.0, 1.0);
-    pub const GRAY: Self = Self::new(0.5, 0.5, 0.5, 1.0);
 
     pub const fn new(r: f32, g: f32, b: f32, a: f32) -> Self {
         Self { r, g, b, a }
     }
 
+    pub fn from_rgb8(r: u8, g: u8, b: u8) -> Self {
+        Self::new(r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0, 1.0)
+    }
+
     pub fn as_array(&self) -> [f32; 4] {
         [self.r, self.g, self.b, self.a]
     }
 }
 
-#[derive(Clone, Copy, Debug)]
+/// Underline styles selected with `SGR 4:n`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum UnderlineStyle {
+    #[default]
+    None,
+    Single,
+    Double,
+    Curly,
+    Dotted,
+    Dashed,
+}
+
+impl UnderlineStyle {
+    const ALL: [Self; 6] = [
+        Self::None,
+        Self::Single,
+        Self::Double,
+        Self::Curly,
+        Self::Dotted,
+        Self::Dashed,
+    ];
+}
+
+/// On/off text attributes, each stored as one bit of `CellAttributes`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Attribute {
+    Bold,
+    Dim,
+    Italic,
+    Blink,
+    Inverse,
+    Hidden,
+    Strikethrough,
+    Overline,
+}
+
+/// Bits of `CellAttributes::bits` above the `Attribute` flags that hold the underline style.
+const UNDERLINE_SHIFT: u16 = 8;
+const UNDERLINE_MASK: u16 = 0b111 << UNDERLINE_SHIFT;
+
+/// A cell's text attributes, packed into a bitfield since every cell of the screen and
+/// scrollback carries them.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CellAttributes {
+    bits: u16,
+    /// SGR 58; `None` draws the underline in the foreground color.
+    pub underline_color: Option<Color>,
+}
+
+impl CellAttributes {
+    pub fn has(self, attribute: Attribute) -> bool {
+        self.bits & (1 << attribute as u16) != 0
+    }
+
+    pub fn set(&mut self, attribute: Attribute, enabled: bool) {
+        let bit = 1 << attribute as u16;
+        if enabled {
+            self.bits |= bit;
+        } else {
+            self.bits &= !bit;
+        }
+    }
+
+    pub fn underline(self) -> UnderlineStyle {
+        UnderlineStyle::ALL[((self.bits & UNDERLINE_MASK) >> UNDERLINE_SHIFT) as usize]
+    }
+
+    pub fn set_underline(&mut self, style: UnderlineStyle) {
+        self.bits = (self.bits & !UNDERLINE_MASK) | ((style as u16) << UNDERLINE_SHIFT);
+    }
+}
+
+/// How many columns a cell's character covers.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CellWidth {
+    #[default]
+    Single,
+    /// The first column of a double-width character.
+    Wide,
+    /// The second column of a double-width character; it has no content of its own.
+    WideSpacer,
+    /// Padding left in the last column when a wide character did not fit and wrapped.
+    LeadingWideSpacer,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct TerminalCell {
     pub character: char,
-    pub fg_color: TerminalColor,
-    pub bg_color: TerminalColor,
-    pub bold: bool,
-    pub italic: bool,
-    pub underline: bool,
+    /// Id of the full grapheme cluster in `TerminalState::graphemes` when the cell holds
+    /// more than `character`, e.g. combining marks or an emoji ZWJ sequence. 0 means none.
+    pub grapheme: u32,
+    pub width: CellWidth,
+    /// Id of the OSC 8 hyperlink in `TerminalState::hyperlinks`, 0 when the cell has none.
+    pub hyperlink: u32,
+    pub fg_color: Color,
+    pub bg_color: Color,
+    pub attributes: CellAttributes,
 }
 
 impl Default for TerminalCell {
     fn default() -> Self {
         Self {
             character: ' ',
-            fg_color: TerminalColor::WHITE,
-            bg_color: TerminalColor::BLACK,
-            bold: false,
-            italic: false,
-            underline: false,
+            grapheme: 0,
+            width: CellWidth::Single,
+            hyperlink: 0,
+            fg_color: Color::DefaultForeground,
+            bg_color: Color::DefaultBackground,
+            attributes: CellAttributes::default(),
+        }
+    }
+}
+
+impl TerminalCell {
+    /// Whether the cell is indistinguishable from an untouched one, so it can be
+    /// dropped from the end of a line when reflowing.
+    fn is_blank(&self) -> bool {
+        self.character == ' '
+            && self.hyperlink == 0
+            && self.bg_color == Color::DefaultBackground
+            && self.attributes == CellAttributes::default()
+    }
+
+    /// Appends the cell's fixed-size encoding, as stored in compressed scrollback.
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        let underline_color = self.attributes.underline_color;
+        out.extend((self.character as u32).to_le_bytes());
+        out.extend(self.grapheme.to_le_bytes());
+        out.extend(self.hyperlink.to_le_bytes());
+        out.extend(self.fg_color.to_bytes());
+        out.extend(self.bg_color.to_bytes());
+        out.extend(self.attributes.bits.to_le_bytes());
+        out.push(self.width as u8);
+        out.push(u8::from(underline_color.is_some()));
+        out.extend(underline_color.map_or([0; 4], Color::to_bytes));
+    }
+
+    /// Reads a cell written by `encode`.
+    pub fn decode(bytes: &[u8; ENCODED_CELL_SIZE]) -> Option<Self> {
+        let word = |at: usize| u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap());
+        let color = |at: usize| Color::from_bytes(bytes[at..at + 4].try_into().unwrap());
+        let width = match bytes[22] {
+            0 => CellWidth::Single,
+            1 => CellWidth::Wide,
+            2 => CellWidth::WideSpacer,
+            3 => CellWidth::LeadingWideSpacer,
+            _ => return None,
+        };
+        let underline_color = match bytes[23] {
+            0 => None,
+            _ => Some(color(24)?),
+        };
+        Some(Self {
+            character: char::from_u32(word(0))?,
+            grapheme: word(4),
+            width,
+            hyperlink: word(8),
+            fg_color: color(12)?,
+            bg_color: color(16)?,
+            attributes: CellAttributes {
+                bits: u16::from_le_bytes([bytes[20], bytes[21]]),
+                underline_color,
+            },
+        })
+    }
+}
+
+/// Size of a cell written by `TerminalCell::encode`.
+pub const ENCODED_CELL_SIZE: usize = 28;
+
+/// Deduplicated values referenced from cells by id, so cells can stay `Copy`. Ids start
+/// at 1. An id stays valid, including for rows in history, until `retain` finds that no
+/// cell refers to it anymore; it is then reused for a later value.
+pub struct Interner<T> {
+    /// The value of each id, at index `id - 1`; `None` once the id is freed.
+    values: Vec<Option<T>>,
+    ids: HashMap<T, u32>,
+    /// Freed ids, reused before new ones.
+    free: Vec<u32>,
+    /// How many values may be held before `needs_collection`.
+    limit: usize,
+}
+
+impl<T> Default for Interner<T> {
+    fn default() -> Self {
+        Self {
+            values: Vec::new(),
+            ids: HashMap::new(),
+            free: Vec::new(),
+            limit: MIN_INTERNER_LIMIT,
+        }
+    }
+}
+
+impl<T: Clone + Eq + Hash> Interner<T> {
+    pub fn intern(&mut self, value: T) -> u32 {
+        if let Some(&id) = self.ids.get(&value) {
+            return id;
+        }
+        let id = match self.free.pop() {
+            Some(id) => {
+                self.values[id as usize - 1] = Some(value.clone());
+                id
+            }
+            None => {
+                self.values.push(Some(value.clone()));
+                self.values.len() as u32
+            }
+        };
+        self.ids.insert(value, id);
+        id
+    }
+
+    /// Changes the value of `id`, which a single cell refers to. Returns the id the cell
+    /// should refer to instead: `id`, unless `value` was already interned.
+    pub fn replace(&mut self, id: u32, value: T) -> u32 {
+        if let Some(old) = self.values[id as usize - 1].take() {
+            self.ids.remove(&old);
+        }
+        self.free.push(id);
+        self.intern(value)
+    }
+
+    pub fn contains(&self, value: &T) -> bool {
+        self.ids.contains_key(value)
+    }
+
+    pub fn get(&self, id: u32) -> Option<&T> {
+        let index = (id as usize).checked_sub(1)?;
+        self.values.get(index)?.as_ref()
+    }
+
+    /// The largest id handed out.
+    pub fn max_id(&self) -> u32 {
+        self.values.len() as u32
+    }
+
+    /// Whether enough values have been interned since the last `retain` that unused ones
+    /// should be freed.
+    pub fn needs_collection(&self) -> bool {
+        self.ids.len() > self.limit
+    }
+
+    /// Frees every id for which `live[id]` is false.
+    pub fn retain(&mut self, live: &[bool]) {
+        for (index, slot) in self.values.iter_mut().enumerate() {
+            if !live.get(index + 1).copied().unwrap_or(false)
+                && let Some(value) = slot.take()
+            {
+                self.ids.remove(&value);
+            }
+        }
+        while self.values.last().is_some_and(Option::is_none) {
+            self.values.pop();
+        }
+        self.free = (1..=self.max_id())
+            .rev()
+            .filter(|&id| self.values[id as usize - 1].is_none())
+            .collect();
+        self.limit = (2 * self.ids.len()).max(MIN_INTERNER_LIMIT);
+    }
+}
+
+/// An OSC 8 hyperlink. Cells sharing an explicit `id` belong to the same link even when
+/// they are not adjacent, e.g. a link wrapped by a full-screen program.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Hyperlink {
+    pub id: Option<String>,
+    pub uri: String,
+}
+
+/// What changed on screen since the last `TerminalState::take_damage`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Damage {
+    /// Everything, e.g. after a resize, a palette change or scrolling the viewport.
+    Full,
+    /// These viewport rows, in ascending order; empty when nothing changed.
+    Rows(Vec<usize>),
+}
+
+/// What the cursor looked like when damage was last taken, to notice it moving.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct DamageCursor {
+    x: usize,
+    y: usize,
+    shown: bool,
+    style: CursorStyle,
+}
+
+/// Longest DCS query string that is buffered; longer ones are dropped.
+const MAX_DCS_LENGTH: usize = 4096;
+/// Room in an OSC string for the `52` and the selection ahead of a clipboard write.
+const OSC52_PARAMS_LENGTH: usize = 16;
+
+/// A DCS query being received.
+enum DcsRequest {
+    /// DECRQSS (`DCS $ q`): the setting to report.
+    Setting(Vec<u8>),
+    /// XTGETTCAP (`DCS + q`): hex-encoded capability names separated by `;`.
+    Termcap(Vec<u8>),
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CursorShape {
+    #[default]
+    Block,
+    Underline,
+    Bar,
+}
+
+/// The cursor's look, set with DECSCUSR.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CursorStyle {
+    pub shape: CursorShape,
+    pub blinking: bool,
+}
+
+impl Default for CursorStyle {
+    fn default() -> Self {
+        Self {
+            shape: CursorShape::Block,
+            blinking: true,
+        }
+    }
+}
+
+impl CursorStyle {
+    /// The style for a DECSCUSR parameter; 0 selects `default`.
+    fn from_decscusr(value: u16, default: Self) -> Option<Self> {
+        let shape = match value {
+            0 => return Some(default),
+            1 | 2 => CursorShape::Block,
+            3 | 4 => CursorShape::Underline,
+            5 | 6 => CursorShape::Bar,
+            _ => return None,
+        };
+        Some(Self {
+            shape,
+            blinking: value % 2 == 1,
+        })
+    }
+
+    fn decscusr(self) -> u16 {
+        let steady = u16::from(!self.blinking);
+        match self.shape {
+            CursorShape::Block => 1 + steady,
+            CursorShape::Underline => 3 + steady,
+            CursorShape::Bar => 5 + steady,
         }
     }
 }
 
+/// Which mouse events are reported to the program (DECSET 9/1000/1002/1003).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MouseTracking {
+    #[default]
+    None,
+    /// Button presses only, without modifiers.
+    X10,
+    /// Presses, releases and the wheel.
+    Normal,
+    /// Also motion while a button is held.
+    ButtonEvent,
+    /// Also motion with no button held.
+    AnyEvent,
+}
+
+/// How mouse reports are encoded (DECSET 1006/1016).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MouseEncoding {
+    /// `CSI M Cb Cx Cy` with values offset by 32; positions past 223 can't be reported.
+    #[default]
+    Default,
+    /// `CSI < Cb ; Cx ; Cy M/m` in cells.
+    Sgr,
+    /// Like `Sgr`, but with positions in pixels.
+    SgrPixels,
+}
+
+/// An OSC 52 request. The application carries these out, as it owns the clipboard and
+/// decides whether reads are allowed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ClipboardRequest {
+    Store {
+        target: ClipboardTarget,
+        text: String,
+    },
+    /// Answer with the target's contents, ending the reply with BEL if `bell_terminated`.
+    Load {
+        target: ClipboardTarget,
+        bell_terminated: bool,
+    },
+    /// A write larger than `clipboard_max_bytes` was ignored.
+    Oversized,
+}
+
+/// Terminal modes set with SM/RM and DECSET/DECRST.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Modes {
+    /// DECAWM: printing past the last column continues on the next line.
+    pub autowrap: bool,
+    /// IRM: printed characters push the rest of the line right instead of overwriting it.
+    pub insert: bool,
+    /// DECSCNM: the screen is drawn with the default foreground and background swapped.
+    pub reverse_video: bool,
+    /// LNM: line feeds also return the cursor to the first column, and Enter sends CR LF.
+    pub newline: bool,
+    /// DECCKM: cursor keys send `SS3` sequences instead of `CSI`.
+    pub application_cursor_keys: bool,
+    /// DECOM: cursor addressing is relative to the scrolling region.
+    pub origin: bool,
+    /// DECTCEM: whether the cursor is drawn.
+    pub show_cursor: bool,
+    /// DECSET 1004: focus changes are reported as `CSI I` / `CSI O`.
+    pub focus_reporting: bool,
+    /// DECSET 2004: pastes are wrapped in `ESC[200~` / `ESC[201~`.
+    pub bracketed_paste: bool,
+}
+
+impl Default for Modes {
+    fn default() -> Self {
+        Self {
+            autowrap: true,
+            insert: false,
+            reverse_video: false,
+            newline: false,
+            application_cursor_keys: false,
+            origin: false,
+            show_cursor: true,
+            focus_reporting: false,
+            bracketed_paste: false,
+        }
+    }
+}
+
+/// Cursor state captured by DECSC and by entering the alternate screen with mode 1049.
+#[derive(Clone, Copy, Debug)]
+pub struct SavedCursor {
+    pub x: usize,
+    pub y: usize,
+    pub fg_color: Color,
+    pub bg_color: Color,
+    pub attributes: CellAttributes,
+    pub charsets: Charsets,
+    pub origin: bool,
+}
+
 pub struct TerminalState {
     pub width: usize,
     pub height: usize,
-    pub cells: Vec<Vec<TerminalCell>>,
+    /// The active grid: the primary screen and its scrollback, or the alternate screen,
+    /// which has none, while it is in use.
+    pub grid: Grid,
+    /// The inactive grid, swapped with `grid` when switching screens.
+    pub inactive_grid: Grid,
+    pub alternate_screen_active: bool,
+    /// Top and bottom rows (inclusive) of the DECSTBM scrolling region.
+    pub scroll_top: usize,
+    pub scroll_bottom: usize,
+    pub modes: Modes,
+    pub mouse_tracking: MouseTracking,
+    pub mouse_encoding: MouseEncoding,
+    pub cursor_style: CursorStyle,
+    /// The style DECSCUSR 0 and resets return to.
+    pub default_cursor_style: CursorStyle,
+    /// G0–G3 designations and shift state, applied to printed characters.
+    pub charsets: Charsets,
+    /// Columns holding a tab stop, one entry per column.
+    pub tab_stops: Vec<bool>,
+    pub saved_cursor: Option<SavedCursor>,
+    inactive_saved_cursor: Option<SavedCursor>,
     pub cursor_x: usize,
     pub cursor_y: usize,
-    pub current_fg_color: TerminalColor,
-    pub current_bg_color: TerminalColor,
-    pub current_bold: bool,
-    pub current_italic: bool,
-    pub current_underline: bool,
+    pub current_fg_color: Color,
+    pub current_bg_color: Color,
+    pub current_attributes: CellAttributes,
+    pub palette: Palette,
+    /// Multi-codepoint grapheme clusters referenced by `TerminalCell::grapheme`.
+    pub graphemes: Interner<String>,
+    pub hyperlinks: Interner<Hyperlink>,
+    /// Grapheme id created for the cluster of the last cell appended to, which no other
+    /// cell refers to, so appending more updates it in place; 0 when none.
+    open_grapheme: u32,
+    /// Window title set with OSC 0/2; empty until a program sets one.
+    pub title: String,
+    /// Icon name set with OSC 0/1.
+    pub icon_name: String,
+    /// Titles saved by `CSI 22 t`, as (title, icon name).
+    title_stack: Vec<(String, String)>,
+    /// Replies to queries, waiting to be written back to the PTY.
+    responses: Vec<u8>,
+    /// The DCS query currently being received, if it's one that is answered.
+    dcs: Option<DcsRequest>,
+    /// OSC 52 requests waiting for the application.
+    pub clipboard_requests: Vec<ClipboardRequest>,
+    /// Largest OSC 52 payload, in decoded bytes, that is written to the clipboard.
+    clipboard_max_bytes: usize,
+    /// The link opened by the last OSC 8, applied to printed characters; 0 when none.
+    pub current_hyperlink: u32,
+    /// Treat East Asian ambiguous-width characters as double width.
+    pub ambiguous_wide: bool,
+    /// How many rows the viewport is scrolled back into `history`; 0 shows the live screen.
     pub scroll_offset: usize,
-    pub history: VecDeque<Vec<TerminalCell>>,
-    pub max_history: usize,
-    pub input_buffer: String,
-    pub prompt: String,
+    /// Whether new output returns the viewport to the live screen.
+    pub scroll_on_output: bool,
+    /// Grid rows changed since the last `take_damage`.
+    damaged_rows: Vec<bool>,
+    /// Set when the whole viewport has to be redrawn.
+    full_damage: bool,
+    /// The cursor, viewport offset and first row id as of the last `take_damage`.
+    damage_cursor: DamageCursor,
+    damage_scroll_offset: usize,
+    damage_first_row: u64,
+    wrap_pending: bool,
+    parser: Parser,
 }
 
 impl TerminalState {
     pub fn new(width: usize, height: usize) -> Self {
-        let mut cells = Vec::new();
-        for _ in 0..height {
-            cells.push(vec![TerminalCell::default(); width]);
-        }
-
         Self {
             width,
             height,
-            cells,
+            grid: Grid::new(width, height, DEFAULT_MAX_HISTORY),
+            inactive_grid: Grid::new(width, height, 0),
+            alternate_screen_active: false,
+            scroll_top: 0,
+            scroll_bottom: height - 1,
+            modes: Modes::default(),
+            mouse_tracking: MouseTracking::None,
+            mouse_encoding: MouseEncoding::Default,
+            cursor_style: CursorStyle::default(),
+            default_cursor_style: CursorStyle::default(),
+            charsets: Charsets::default(),
+            tab_stops: default_tab_stops(width),
+            saved_cursor: None,
+            inactive_saved_cursor: None,
             cursor_x: 0,
             cursor_y: 0,
-            current_fg_color: TerminalColor::WHITE,
-            current_bg_color: TerminalColor::BLACK,
-            current_bold: false,
-            current_italic: false,
-            current_underline: false,
+            current_fg_color: Color::DefaultForeground,
+            current_bg_color: Color::DefaultBackground,
+            current_attributes: CellAttributes::default(),
+            palette: Palette::default(),
+            graphemes: Interner::default(),
+            hyperlinks: Interner::default(),
+            open_grapheme: 0,
+            current_hyperlink: 0,
+            title: String::new(),
+            icon_name: String::new(),
+            title_stack: Vec::new(),
+            responses: Vec::new(),
+            dcs: None,
+            clipboard_requests: Vec::new(),
+            clipboard_max_bytes: 1 << 20,
+            ambiguous_wide: false,
             scroll_offset: 0,
-            history: VecDeque::new(),
-            max_history: 1000,
-            input_buffer: String::new(),
-            prompt: "$ ".to_string(),
+            scroll_on_output: true,
+            damaged_rows: vec![false; height],
+            full_damage: true,
+            damage_cursor: DamageCursor {
+                x: 0,
+                y: 0,
+                shown: true,
+                style: CursorStyle::default(),
+            },
+            damage_scroll_offset: 0,
+            damage_first_row: 0,
+            wrap_pending: false,
+            parser: Parser::new(),
         }
     }
 
+    /// Resizes both screens. The primary screen and its history are rewrapped to the new
+    /// width; the alternate screen is only cropped or padded, as its owner redraws it.
     pub fn resize(&mut self, new_width: usize, new_height: usize) {
+        if new_width == self.width && new_height == self.height {
+            return;
+        }
         self.width = new_width;
         self.height = new_height;
-        
-        self.cells.resize(new_height, Vec::new());
-        for row in &mut self.cells {
-            row.resize(new_width, TerminalCell::default());
+
+        if self.alternate_screen_active {
+            let anchor = self.inactive_saved_cursor.map(|saved| (saved.x, saved.y));
+            let (x, y) = self.reflow_primary(anchor);
+            if let Some(saved) = &mut self.inactive_saved_cursor {
+                saved.x = x;
+                saved.y = y;
+            }
+
+            self.grid.resize(new_width, new_height);
+        } else {
+            let (x, y) = self.reflow_primary(Some((self.cursor_x, self.cursor_y)));
+            self.cursor_x = x;
+            self.cursor_y = y;
+
+            self.inactive_grid.resize(new_width, new_height);
         }
 
         if self.cursor_x >= new_width {
@@ -110,168 +661,1926 @@ impl TerminalState {
         if self.cursor_y >= new_height {
             self.cursor_y = new_height.saturating_sub(1);
         }
+        if let Some(saved) = &mut self.saved_cursor {
+            saved.x = saved.x.min(new_width - 1);
+            saved.y = saved.y.min(new_height - 1);
+        }
+        self.scroll_offset = self.scroll_offset.min(self.history_len());
+        self.prepare_viewport();
+        self.scroll_top = 0;
+        self.scroll_bottom = new_height - 1;
+        self.tab_stops = default_tab_stops(new_width);
+        self.damaged_rows = vec![false; new_height];
+        self.full_damage = true;
+        self.wrap_pending = false;
     }
 
-    pub fn put_char(&mut self, ch: char) {
-        if ch == '\n' {
-            self.newline();
-            return;
+    /// Marks a grid row as needing a redraw.
+    fn damage_row(&mut self, y: usize) {
+        if let Some(damaged) = self.damaged_rows.get_mut(y) {
+            *damaged = true;
         }
+    }
 
-        if ch == '\r' {
-            self.cursor_x = 0;
-            return;
+    fn damage_rows(&mut self, rows: std::ops::Range<usize>) {
+        let end = rows.end.min(self.damaged_rows.len());
+        self.damaged_rows[rows.start.min(end)..end].fill(true);
+    }
+
+    /// Returns and clears what changed since the last call. Rows under the cursor's old
+    /// and new positions count as changed when it moved or changed style.
+    pub fn take_damage(&mut self) -> Damage {
+        let cursor = DamageCursor {
+            x: self.cursor_x,
+            y: self.cursor_y,
+            shown: self.modes.show_cursor,
+            style: self.cursor_style,
+        };
+        if cursor != self.damage_cursor {
+            self.damage_row(self.damage_cursor.y);
+            self.damage_row(cursor.y);
+            self.damage_cursor = cursor;
+        }
+
+        let offset = self.viewport_offset();
+        if offset != self.damage_scroll_offset {
+            self.damage_scroll_offset = offset;
+            self.full_damage = true;
+        }
+        // Dropping old history moves the rows of a scrolled-back view without changing
+        // the offset, once it can't grow past the top of the history
+        let first_row = self.grid.first_row_id();
+        if first_row != self.damage_first_row {
+            self.damage_first_row = first_row;
+            self.full_damage |= offset > 0;
+        }
+
+        let damage = if self.full_damage {
+            Damage::Full
+        } else {
+            let rows = self
+                .damaged_rows
+                .iter()
+                .enumerate()
+                .filter(|&(y, &damaged)| damaged && y + offset < self.height)
+                .map(|(y, _)| y + offset)
+                .collect();
+            Damage::Rows(rows)
+        };
+        self.full_damage = false;
+        self.damaged_rows.fill(false);
+        damage
+    }
+
+    /// RIS: returns to the power-on state. Scrollback and settings from the config are kept.
+    fn full_reset(&mut self) {
+        let (width, height) = (self.width, self.height);
+        let mut fresh = Self::new(width, height);
+        let mut primary = std::mem::take(self.primary_grid_mut());
+        let (history, _) = primary.take_rows();
+        primary.set_rows(history, vec![Row::new(width); height], width);
+        fresh.grid = primary;
+        fresh.scroll_on_output = self.scroll_on_output;
+        fresh.ambiguous_wide = self.ambiguous_wide;
+        fresh.set_clipboard_max_bytes(self.clipboard_max_bytes);
+        fresh.default_cursor_style = self.default_cursor_style;
+        fresh.cursor_style = self.default_cursor_style;
+        fresh.responses = std::mem::take(&mut self.responses);
+        fresh.clipboard_requests = std::mem::take(&mut self.clipboard_requests);
+        fresh.title = std::mem::take(&mut self.title);
+        fresh.icon_name = std::mem::take(&mut self.icon_name);
+        // The history still refers to these
+        fresh.graphemes = std::mem::take(&mut self.graphemes);
+        fresh.hyperlinks = std::mem::take(&mut self.hyperlinks);
+        *self = fresh;
+    }
+
+    /// DECSTR: resets modes, margins, the pen, charsets and the saved cursor, leaving the
+    /// screen contents and cursor position alone.
+    fn soft_reset(&mut self) {
+        self.modes = Modes::default();
+        self.scroll_top = 0;
+        self.scroll_bottom = self.height - 1;
+        self.reset_formatting();
+        self.charsets = Charsets::default();
+        self.saved_cursor = None;
+        self.cursor_style = self.default_cursor_style;
+        self.wrap_pending = false;
+    }
+
+    /// Rewraps the primary screen and its history to `self.width` x `self.height`, keeping
+    /// `anchor` (a primary-screen cursor position) on the same character. Returns the
+    /// anchor's new position. Compressed history is decompressed and compressed again a
+    /// block at a time, but only when the width changed.
+    fn reflow_primary(&mut self, anchor: Option<(usize, usize)>) -> (usize, usize) {
+        let (width, height) = (self.width, self.height);
+        let mut primary = std::mem::take(self.primary_grid_mut());
+        let mut rewrap = Rewrap::new(width);
+        if primary.width() != width {
+            let cold = primary.take_cold();
+            cold.for_each_row_from(0, &Row::default(), &mut |row| {
+                rewrap.push(row, None);
+                // Rows this far from the end stay in history, whatever follows
+                while rewrap.rows.len() >= BLOCK_ROWS + height {
+                    let block: Vec<Row> = rewrap.rows.drain(..BLOCK_ROWS).collect();
+                    primary.push_cold_block(&block);
+                }
+            });
+        }
+        let (mut rows, screen) = primary.take_rows();
+        let grid_start = rows.len();
+        rows.extend(screen);
+
+        // Blank rows below the anchor would otherwise push content into history
+        let anchor_row = anchor.map(|(_, y)| grid_start + y);
+        let keep_after = anchor_row.unwrap_or(grid_start.saturating_sub(1));
+        while rows.len() > keep_after + 1
+            && rows
+                .last()
+                .is_some_and(|row| !row.wrapped && row.iter().all(TerminalCell::is_blank))
+        {
+            rows.pop();
+        }
+
+        for (index, row) in rows.iter().enumerate() {
+            let anchor_x = (Some(index) == anchor_row).then(|| anchor.map_or(0, |(x, _)| x));
+            rewrap.push(row, anchor_x);
+        }
+        let Rewrap {
+            rows: mut new_rows,
+            anchor: anchor_position,
+            ..
+        } = rewrap;
+
+        // Whatever doesn't fit on screen goes to history, as long as the anchor stays visible
+        let (anchor_x, anchor_y) = anchor_position.unwrap_or((0, 0));
+        let mut split = new_rows.len().saturating_sub(height);
+        if anchor.is_some() {
+            split = split.min(anchor_y);
         }
+        let mut screen = new_rows.split_off(split);
+        screen.truncate(height);
+        screen.resize(height, Row::new(width));
+        primary.set_rows(new_rows, screen, width);
+        *self.primary_grid_mut() = primary;
+        (anchor_x, anchor_y.saturating_sub(split))
+    }
+
+    /// Column width of `ch`: 0 for combining characters, 2 for East Asian wide ones.
+    fn char_width(&self, ch: char) -> usize {
+        let width = if self.ambiguous_wide {
+            ch.width_cjk()
+        } else {
+            ch.width()
+        };
+        width.unwrap_or(1)
+    }
 
-        if ch == '\t' {
-            let tab_stop = 8;
-            let spaces = tab_stop - (self.cursor_x % tab_stop);
-            for _ in 0..spaces {
-                self.put_char(' ');
+    pub fn put_char(&mut self, ch: char) {
+        if ch.is_control() {
+            if (ch as u32) < 0x20 {
+                self.execute(ch as u8);
             }
             return;
         }
 
-        if ch == '\x08' {
-            if self.cursor_x > 0 {
-                self.cursor_x -= 1;
-                self.cells[self.cursor_y][self.cursor_x] = TerminalCell::default();
-            }
+        let char_width = self.char_width(ch);
+        if char_width == 0 || self.joins_previous_cell(ch) {
+            self.append_to_previous_cell(ch);
             return;
         }
+        let char_width = char_width.min(self.width);
+
+        if self.wrap_pending {
+            self.grid[self.cursor_y].wrapped = true;
+            self.newline();
+        }
+        self.damage_row(self.cursor_y);
 
-        if self.cursor_x >= self.width {
+        // A wide character never straddles the right edge; it moves to the next line, or
+        // back a column when autowrap is off
+        if char_width == 2 && self.cursor_x + 1 >= self.width && !self.modes.autowrap {
+            self.cursor_x = self.width - 2;
+        } else if char_width == 2 && self.cursor_x + 1 >= self.width {
+            self.clear_wide_char_halves(self.cursor_x, 1);
+            self.grid[self.cursor_y][self.cursor_x] = TerminalCell {
+                width: CellWidth::LeadingWideSpacer,
+                ..self.blank_cell()
+            };
+            self.grid[self.cursor_y].wrapped = true;
             self.newline();
+            self.damage_row(self.cursor_y);
         }
 
-        self.cells[self.cursor_y][self.cursor_x] = TerminalCell {
+        if self.modes.insert {
+            self.clear_wide_char_halves(self.cursor_x, 1);
+            self.insert_chars(char_width);
+        }
+        let (x, y) = (self.cursor_x, self.cursor_y);
+        self.clear_wide_char_halves(x, char_width);
+        let cell = TerminalCell {
             character: ch,
+            grapheme: 0,
+            width: CellWidth::Single,
+            hyperlink: self.current_hyperlink,
             fg_color: self.current_fg_color,
             bg_color: self.current_bg_color,
-            bold: self.current_bold,
-            italic: self.current_italic,
-            underline: self.current_underline,
+            attributes: self.current_attributes,
         };
+        if char_width == 2 {
+            self.grid[y][x] = TerminalCell {
+                width: CellWidth::Wide,
+                ..cell
+            };
+            self.grid[y][x + 1] = TerminalCell {
+                character: ' ',
+                width: CellWidth::WideSpacer,
+                ..cell
+            };
+        } else {
+            self.grid[y][x] = cell;
+        }
 
-        self.cursor_x += 1;
+        self.advance_cursor(x + char_width);
     }
 
-    pub fn write_str(&mut self, s: &str) {
-        for ch in s.chars() {
-            self.put_char(ch);
+    /// Moves the cursor to column `x` after printing. Writing the last column leaves the
+    /// cursor there until the next printable character.
+    fn advance_cursor(&mut self, x: usize) {
+        if x >= self.width {
+            self.cursor_x = self.width - 1;
+            self.wrap_pending = self.modes.autowrap;
+        } else {
+            self.cursor_x = x;
+            self.wrap_pending = false;
         }
     }
 
-    pub fn newline(&mut self) {
-        self.cursor_x = 0;
-        self.cursor_y += 1;
+    /// Blanks the other half of any wide character that overlaps `count` cells from `x`
+    /// on the cursor row, so no orphaned half is left behind when they are overwritten.
+    fn clear_wide_char_halves(&mut self, x: usize, count: usize) {
+        let blank = self.blank_cell();
+        let row = &mut self.grid[self.cursor_y];
+        if row[x].width == CellWidth::WideSpacer && x > 0 {
+            row[x - 1] = blank;
+        }
+        let last = x + count - 1;
+        if row[last].width == CellWidth::Wide && last + 1 < row.len() {
+            row[last + 1] = blank;
+        }
+    }
 
-        if self.cursor_y >= self.height {
-            self.scroll_up();
-            self.cursor_y = self.height - 1;
+    /// The cell holding the most recently printed character: the one left of the cursor,
+    /// or under it while a wrap is pending.
+    fn previous_cell_position(&self) -> Option<usize> {
+        let x = if self.wrap_pending {
+            self.cursor_x
+        } else {
+            self.cursor_x.checked_sub(1)?
+        };
+        match self.grid[self.cursor_y][x].width {
+            CellWidth::WideSpacer => x.checked_sub(1),
+            CellWidth::LeadingWideSpacer => None,
+            _ => Some(x),
         }
     }
 
-    pub fn scroll_up(&mut self) {
-        if let Some(first_row) = self.cells.first().cloned() {
-            self.history.push_back(first_row);
-            if self.history.len() > self.max_history {
-                self.history.pop_front();
-            }
+    /// The text of a cell: its whole grapheme cluster if it has one.
+    pub fn cell_text(&self, cell: &TerminalCell) -> Cow<'_, str> {
+        match self.graphemes.get(cell.grapheme) {
+            Some(cluster) => Cow::Borrowed(cluster.as_str()),
+            None => Cow::Owned(cell.character.to_string()),
+        }
+    }
+
+    /// Whether `ch` continues the previous cell's cluster even though it has a width of
+    /// its own: the character after a ZWJ, or the second regional indicator of a flag.
+    fn joins_previous_cell(&self, ch: char) -> bool {
+        let Some(x) = self.previous_cell_position() else {
+            return false;
+        };
+        let cell = &self.grid[self.cursor_y][x];
+        // Most cells hold a single character; skip building their text
+        if cell.grapheme == 0 {
+            return cell.character == ZERO_WIDTH_JOINER
+                || (is_regional_indicator(ch) && is_regional_indicator(cell.character));
+        }
+        let text = self.cell_text(cell);
+        if text.ends_with(ZERO_WIDTH_JOINER) {
+            return true;
         }
 
-        self.cells.remove(0);
-        self.cells.push(vec![TerminalCell::default(); self.width]);
+        let mut chars = text.chars();
+        is_regional_indicator(ch)
+            && chars.next().is_some_and(is_regional_indicator)
+            && chars.next().is_none()
     }
 
-    pub fn clear(&mut self) {
-        for row in &mut self.cells {
-            for cell in row {
-                *cell = TerminalCell::default();
-            }
+    /// Adds `ch` to the cluster of the previously printed cell. Variation selectors and
+    /// flags may change the cluster's width, which moves the cursor with it.
+    fn append_to_previous_cell(&mut self, ch: char) {
+        let Some(x) = self.previous_cell_position() else {
+            return;
+        };
+        let y = self.cursor_y;
+        self.damage_row(y);
+        let cell = self.grid[y][x];
+
+        // Only the finished cluster is kept; its prefixes are replaced as it grows
+        let mut cluster = self.cell_text(&cell).into_owned();
+        cluster.push(ch);
+        let new = !self.graphemes.contains(&cluster);
+        let id = if cell.grapheme != 0 && cell.grapheme == self.open_grapheme {
+            self.graphemes.replace(cell.grapheme, cluster)
+        } else {
+            self.graphemes.intern(cluster)
+        };
+        self.open_grapheme = if new { id } else { 0 };
+        self.grid[y][x].grapheme = id;
+
+        let widen = ch == EMOJI_PRESENTATION || is_regional_indicator(ch);
+        if widen && cell.width == CellWidth::Single && x + 1 < self.width {
+            self.grid[y][x].width = CellWidth::Wide;
+            self.grid[y][x + 1] = TerminalCell {
+                character: ' ',
+                grapheme: 0,
+                width: CellWidth::WideSpacer,
+                ..cell
+            };
+            self.advance_cursor(x + 2);
+        } else if ch == TEXT_PRESENTATION && cell.width == CellWidth::Wide {
+            self.grid[y][x].width = CellWidth::Single;
+            self.grid[y][x + 1] = TerminalCell {
+                character: ' ',
+                grapheme: 0,
+                width: CellWidth::Single,
+                ..cell
+            };
+            self.advance_cursor(x + 1);
         }
+    }
+
+    pub fn newline(&mut self) {
         self.cursor_x = 0;
-        self.cursor_y = 0;
+        self.linefeed();
     }
 
-    pub fn set_fg_color(&mut self, color: TerminalColor) {
-        self.current_fg_color = color;
+    pub fn linefeed(&mut self) {
+        self.wrap_pending = false;
+        if self.cursor_y == self.scroll_bottom {
+            self.scroll_up(1);
+        } else if self.cursor_y + 1 < self.height {
+            self.cursor_y += 1;
+        }
     }
 
-    pub fn set_bg_color(&mut self, color: TerminalColor) {
-        self.current_bg_color = color;
+    pub fn reverse_index(&mut self) {
+        self.wrap_pending = false;
+        if self.cursor_y == self.scroll_top {
+            self.scroll_down(1);
+        } else if self.cursor_y > 0 {
+            self.cursor_y -= 1;
+        }
     }
 
-    pub fn set_bold(&mut self, bold: bool) {
-        self.current_bold = bold;
+    fn is_full_screen_region(&self) -> bool {
+        self.scroll_top == 0 && self.scroll_bottom == self.height - 1
     }
 
-    pub fn set_italic(&mut self, italic: bool) {
-        self.current_italic = italic;
+    /// Scrolls the scrolling region up. Only rows leaving a full-screen region on the
+    /// primary screen are kept in the history.
+    pub fn scroll_up(&mut self, count: usize) {
+        let (top, bottom) = (self.scroll_top, self.scroll_bottom);
+        let count = count.min(bottom - top + 1);
+        let save_history = !self.alternate_screen_active && self.is_full_screen_region();
+
+        let blank = self.blank_cell();
+        self.damage_rows(top..bottom + 1);
+        self.grid.scroll_up(top, bottom, count, blank, save_history);
+
+        // Keep a scrolled-back viewport on the same content while output arrives
+        if save_history && self.scroll_offset > 0 {
+            self.scroll_offset = (self.scroll_offset + count).min(self.history_len());
+        }
     }
 
-    pub fn set_underline(&mut self, underline: bool) {
-        self.current_underline = underline;
+    pub fn scroll_down(&mut self, count: usize) {
+        let (top, bottom) = (self.scroll_top, self.scroll_bottom);
+        let blank = self.blank_cell();
+        self.damage_rows(top..bottom + 1);
+        self.grid.scroll_down(top, bottom, count, blank);
     }
 
-    pub fn reset_formatting(&mut self) {
-        self.current_fg_color = TerminalColor::WHITE;
-        self.current_bg_color = TerminalColor::BLACK;
-        self.current_bold = false;
-        self.current_italic = false;
-        self.current_underline = false;
-    }
-
-    pub fn handle_input(&mut self, ch: char) {
-        match ch {
-            '\x08' => {
-                if !self.input_buffer.is_empty() {
-                    self.input_buffer.pop();
-                    self.put_char('\x08');
-                }
+    /// DECSTBM. `top` and `bottom` are 1-based; 0 selects the screen edge.
+    pub fn set_scrolling_region(&mut self, top: usize, bottom: usize) {
+        let top = top.max(1) - 1;
+        let bottom = if bottom == 0 {
+            self.height - 1
+        } else {
+            (bottom - 1).min(self.height - 1)
+        };
+
+        if top < bottom {
+            self.scroll_top = top;
+            self.scroll_bottom = bottom;
+            self.set_cursor_position(0, 0);
+        }
+    }
+
+    /// An empty cell carrying the current background, as used by erase and scroll operations.
+    fn blank_cell(&self) -> TerminalCell {
+        TerminalCell {
+            bg_color: self.current_bg_color,
+            ..TerminalCell::default()
+        }
+    }
+
+    pub fn save_cursor(&mut self) {
+        self.saved_cursor = Some(SavedCursor {
+            x: self.cursor_x,
+            y: self.cursor_y,
+            fg_color: self.current_fg_color,
+            bg_color: self.current_bg_color,
+            attributes: self.current_attributes,
+            charsets: self.charsets,
+            origin: self.modes.origin,
+        });
+    }
+
+    /// Restores the state saved by `save_cursor`, or homes the cursor and resets attributes
+    /// when nothing was saved.
+    pub fn restore_cursor(&mut self) {
+        match self.saved_cursor {
+            Some(saved) => {
+                self.move_cursor_to(saved.x, saved.y);
+                self.current_fg_color = saved.fg_color;
+                self.current_bg_color = saved.bg_color;
+                self.current_attributes = saved.attributes;
+                self.charsets = saved.charsets;
+                self.modes.origin = saved.origin;
             }
-            '\r' | '\n' => {
-                self.newline();
-                self.execute_command();
-                let prompt = self.prompt.clone();
-                self.write_str(&prompt);
+            None => {
+                self.move_cursor_to(0, 0);
+                self.reset_formatting();
+                self.charsets = Charsets::default();
+                self.modes.origin = false;
             }
-            ch if ch.is_control() => {
+        }
+    }
+
+    fn swap_screens(&mut self) {
+        std::mem::swap(&mut self.grid, &mut self.inactive_grid);
+        std::mem::swap(&mut self.saved_cursor, &mut self.inactive_saved_cursor);
+        self.alternate_screen_active = !self.alternate_screen_active;
+        self.full_damage = true;
+        self.wrap_pending = false;
+    }
+
+    pub fn enter_alternate_screen(&mut self, save_cursor: bool, clear: bool) {
+        if self.alternate_screen_active {
+            return;
+        }
+        if save_cursor {
+            self.save_cursor();
+        }
+        self.swap_screens();
+        if clear {
+            let blank = self.blank_cell();
+            for row in self.grid.screen_mut() {
+                row.clear(blank);
             }
-            ch => {
-                self.input_buffer.push(ch);
-                self.put_char(ch);
+        }
+    }
+
+    pub fn exit_alternate_screen(&mut self, restore_cursor: bool, clear: bool) {
+        if !self.alternate_screen_active {
+            return;
+        }
+        if clear {
+            let blank = self.blank_cell();
+            for row in self.grid.screen_mut() {
+                row.clear(blank);
             }
         }
+        self.swap_screens();
+        if restore_cursor {
+            self.restore_cursor();
+        }
     }
 
-    fn execute_command(&mut self) {
-        let command = self.input_buffer.trim().to_string();
-        self.input_buffer.clear();
+    /// SM/RM (`CSI Pm h` / `CSI Pm l`).
+    pub fn set_mode(&mut self, mode: u16, enabled: bool) {
+        match mode {
+            4 => self.modes.insert = enabled,
+            20 => self.modes.newline = enabled,
+            _ => {}
+        }
+    }
 
-        match command.as_str() {
-            "" => {}
-            "clear" => self.clear(),
-            "help" => {
-                self.write_str("Available commands:\n");
-                self.write_str("  clear - Clear the terminal\n");
-                self.write_str("  help  - Show this help message\n");
-                self.write_str("  exit  - Exit the terminal\n");
+    /// DECSET/DECRST (`CSI ? Pm h` / `CSI ? Pm l`).
+    pub fn set_private_mode(&mut self, mode: u16, enabled: bool) {
+        match (mode, enabled) {
+            (47, true) => self.enter_alternate_screen(false, false),
+            (47, false) => self.exit_alternate_screen(false, false),
+            (1047, true) => self.enter_alternate_screen(false, false),
+            (1047, false) => self.exit_alternate_screen(false, true),
+            (1049, true) => self.enter_alternate_screen(true, true),
+            (1049, false) => self.exit_alternate_screen(true, false),
+            (1, _) => self.modes.application_cursor_keys = enabled,
+            (5, _) => {
+                self.modes.reverse_video = enabled;
+                self.full_damage = true;
+            }
+            (6, _) => {
+                self.modes.origin = enabled;
+                self.set_cursor_position(0, 0);
+            }
+            (7, _) => {
+                self.modes.autowrap = enabled;
+                if !enabled {
+                    self.wrap_pending = false;
+                }
             }
-            "exit" => {
-                self.write_str("Goodbye!\n");
+            (9 | 1000 | 1002 | 1003, _) => {
+                let tracking = match mode {
+                    9 => MouseTracking::X10,
+                    1000 => MouseTracking::Normal,
+                    1002 => MouseTracking::ButtonEvent,
+                    _ => MouseTracking::AnyEvent,
+                };
+                if enabled {
+                    self.mouse_tracking = tracking;
+                } else if self.mouse_tracking == tracking {
+                    self.mouse_tracking = MouseTracking::None;
+                }
             }
-            _ => {
-                self.write_str(&format!("Unknown command: {}\n", command));
+            (1006 | 1016, _) => {
+                let encoding = if mode == 1006 {
+                    MouseEncoding::Sgr
+                } else {
+                    MouseEncoding::SgrPixels
+                };
+                if enabled {
+                    self.mouse_encoding = encoding;
+                } else if self.mouse_encoding == encoding {
+                    self.mouse_encoding = MouseEncoding::Default;
+                }
             }
+            (12, _) => self.cursor_style.blinking = enabled,
+            (25, _) => self.modes.show_cursor = enabled,
+            (1004, _) => self.modes.focus_reporting = enabled,
+            (2004, _) => self.modes.bracketed_paste = enabled,
+            _ => {}
         }
     }
 
-    pub fn get_visible_cells(&self) -> &[Vec<TerminalCell>] {
-        &self.cells
+    pub fn move_cursor_to(&mut self, x: usize, y: usize) {
+        self.cursor_x = x.min(self.width - 1);
+        self.cursor_y = y.min(self.height - 1);
+        self.wrap_pending = false;
     }
 
-    pub fn get_cursor_position(&self) -> (usize, usize) {
-        (self.cursor_x, self.cursor_y)
+    /// Moves to a position given in the addressing used by CUP: relative to the
+    /// scrolling region and confined to it while origin mode is set.
+    pub fn set_cursor_position(&mut self, x: usize, y: usize) {
+        let y = if self.modes.origin {
+            (self.scroll_top + y).min(self.scroll_bottom)
+        } else {
+            y
+        };
+        self.move_cursor_to(x, y);
+    }
+
+    fn move_cursor_by(&mut self, dx: isize) {
+        let x = self.cursor_x.saturating_add_signed(dx);
+        self.move_cursor_to(x, self.cursor_y);
+    }
+
+    /// CUU stops at the top margin when starting inside the scrolling region.
+    fn move_cursor_up(&mut self, count: usize) {
+        let limit = if self.cursor_y >= self.scroll_top {
+            self.scroll_top
+        } else {
+            0
+        };
+        let y = self.cursor_y.saturating_sub(count).max(limit);
+        self.move_cursor_to(self.cursor_x, y);
+    }
+
+    /// CUD stops at the bottom margin when starting inside the scrolling region.
+    fn move_cursor_down(&mut self, count: usize) {
+        let limit = if self.cursor_y <= self.scroll_bottom {
+            self.scroll_bottom
+        } else {
+            self.height - 1
+        };
+        let y = (self.cursor_y + count).min(limit);
+        self.move_cursor_to(self.cursor_x, y);
+    }
+
+    /// HT/CHT: moves the cursor to the `count`th next tab stop, or the last column.
+    /// Cells passed over are left untouched.
+    fn tab_forward(&mut self, count: usize) {
+        let mut x = self.cursor_x;
+        for _ in 0..count {
+            x = (x + 1..self.width)
+                .find(|&column| self.tab_stops[column])
+                .unwrap_or(self.width - 1);
+        }
+        self.cursor_x = x;
+        self.wrap_pending = false;
+    }
+
+    /// CBT: moves the cursor to the `count`th previous tab stop, or the first column.
+    fn tab_backward(&mut self, count: usize) {
+        let mut x = self.cursor_x;
+        for _ in 0..count {
+            x = (0..x)
+                .rev()
+                .find(|&column| self.tab_stops[column])
+                .unwrap_or(0);
+        }
+        self.cursor_x = x;
+        self.wrap_pending = false;
+    }
+
+    /// TBC: 0 clears the stop at the cursor column, 3 clears all of them.
+    fn clear_tab_stops(&mut self, mode: u16) {
+        match mode {
+            0 => self.tab_stops[self.cursor_x] = false,
+            3 => self.tab_stops.fill(false),
+            _ => {}
+        }
+    }
+
+    pub fn erase_in_display(&mut self, mode: u16) {
+        let blank = self.blank_cell();
+        match mode {
+            0 => {
+                self.erase_in_line(0);
+                self.damage_rows(self.cursor_y + 1..self.height);
+                for row in self.grid.screen_mut().skip(self.cursor_y + 1) {
+                    row.clear(blank);
+                }
+            }
+            1 => {
+                self.erase_in_line(1);
+                self.damage_rows(0..self.cursor_y);
+                for row in self.grid.screen_mut().take(self.cursor_y) {
+                    row.clear(blank);
+                }
+            }
+            2 => {
+                self.damage_rows(0..self.height);
+                for row in self.grid.screen_mut() {
+                    row.clear(blank);
+                }
+            }
+            3 => {
+                self.primary_grid_mut().clear_history();
+                self.scroll_offset = 0;
+                self.full_damage = true;
+            }
+            _ => {}
+        }
+    }
+
+    pub fn erase_in_line(&mut self, mode: u16) {
+        let blank = self.blank_cell();
+        self.damage_row(self.cursor_y);
+        let row = &mut self.grid[self.cursor_y];
+        match mode {
+            0 => {
+                row[self.cursor_x..].fill(blank);
+                row.wrapped = false;
+            }
+            1 => row[..=self.cursor_x].fill(blank),
+            2 => row.clear(blank),
+            _ => {}
+        }
+        self.wrap_pending = false;
+    }
+
+    pub fn erase_chars(&mut self, count: usize) {
+        let blank = self.blank_cell();
+        let end = (self.cursor_x + count).min(self.width);
+        self.damage_row(self.cursor_y);
+        self.grid[self.cursor_y][self.cursor_x..end].fill(blank);
+        self.wrap_pending = false;
+    }
+
+    pub fn insert_chars(&mut self, count: usize) {
+        let blank = self.blank_cell();
+        let count = count.min(self.width - self.cursor_x);
+        self.damage_row(self.cursor_y);
+        let row = &mut self.grid[self.cursor_y];
+        row[self.cursor_x..].rotate_right(count);
+        row[self.cursor_x..self.cursor_x + count].fill(blank);
+        self.wrap_pending = false;
+    }
+
+    pub fn delete_chars(&mut self, count: usize) {
+        let blank = self.blank_cell();
+        let count = count.min(self.width - self.cursor_x);
+        let width = self.width;
+        self.damage_row(self.cursor_y);
+        let row = &mut self.grid[self.cursor_y];
+        row[self.cursor_x..].rotate_left(count);
+        row[width - count..].fill(blank);
+        self.wrap_pending = false;
+    }
+
+    /// IL: only acts when the cursor is inside the scrolling region.
+    pub fn insert_lines(&mut self, count: usize) {
+        if self.cursor_y < self.scroll_top || self.cursor_y > self.scroll_bottom {
+            return;
+        }
+        let count = count.min(self.scroll_bottom + 1 - self.cursor_y);
+        let blank = self.blank_cell();
+        self.damage_rows(self.cursor_y..self.scroll_bottom + 1);
+        self.grid
+            .scroll_down(self.cursor_y, self.scroll_bottom, count, blank);
+        self.cursor_x = 0;
+        self.wrap_pending = false;
+    }
+
+    /// DL: only acts when the cursor is inside the scrolling region.
+    pub fn delete_lines(&mut self, count: usize) {
+        if self.cursor_y < self.scroll_top || self.cursor_y > self.scroll_bottom {
+            return;
+        }
+        let count = count.min(self.scroll_bottom + 1 - self.cursor_y);
+        let blank = self.blank_cell();
+        self.damage_rows(self.cursor_y..self.scroll_bottom + 1);
+        self.grid
+            .scroll_up(self.cursor_y, self.scroll_bottom, count, blank, false);
+        self.cursor_x = 0;
+        self.wrap_pending = false;
+    }
+
+    pub fn set_fg_color(&mut self, color: Color) {
+        self.current_fg_color = color;
+    }
+
+    pub fn set_bg_color(&mut self, color: Color) {
+        self.current_bg_color = color;
+    }
+
+    pub fn set_bold(&mut self, bold: bool) {
+        self.current_attributes.set(Attribute::Bold, bold);
+    }
+
+    pub fn set_italic(&mut self, italic: bool) {
+        self.current_attributes.set(Attribute::Italic, italic);
+    }
+
+    pub fn set_underline(&mut self, underline: UnderlineStyle) {
+        self.current_attributes.set_underline(underline);
+    }
+
+    pub fn reset_formatting(&mut self) {
+        self.current_fg_color = Color::DefaultForeground;
+        self.current_bg_color = Color::DefaultBackground;
+        self.current_attributes = CellAttributes::default();
+    }
+
+    /// SGR: applies each parameter group in order. Groups may use either the
+    /// `38;2;r;g;b` form or the colon sub-parameter form `38:2::r:g:b`.
+    pub fn set_graphics_rendition(&mut self, params: &Params) {
+        let mut groups = params.iter();
+        if params.get(0).is_none() {
+            self.reset_formatting();
+            return;
+        }
+
+        while let Some(group) = groups.next() {
+            match group[0] {
+                0 => self.reset_formatting(),
+                1 => self.set_bold(true),
+                2 => self.current_attributes.set(Attribute::Dim, true),
+                3 => self.set_italic(true),
+                4 => {
+                    let style = match group.get(1) {
+                        None | Some(1) => UnderlineStyle::Single,
+                        Some(0) => UnderlineStyle::None,
+                        Some(2) => UnderlineStyle::Double,
+                        Some(3) => UnderlineStyle::Curly,
+                        Some(4) => UnderlineStyle::Dotted,
+                        Some(5) => UnderlineStyle::Dashed,
+                        Some(_) => continue,
+                    };
+                    self.set_underline(style);
+                }
+                5 | 6 => self.current_attributes.set(Attribute::Blink, true),
+                7 => self.current_attributes.set(Attribute::Inverse, true),
+                8 => self.current_attributes.set(Attribute::Hidden, true),
+                9 => self.current_attributes.set(Attribute::Strikethrough, true),
+                21 => self.set_underline(UnderlineStyle::Double),
+                22 => {
+                    self.current_attributes.set(Attribute::Bold, false);
+                    self.current_attributes.set(Attribute::Dim, false);
+                }
+                23 => self.set_italic(false),
+                24 => self.set_underline(UnderlineStyle::None),
+                25 => self.current_attributes.set(Attribute::Blink, false),
+                27 => self.current_attributes.set(Attribute::Inverse, false),
+                28 => self.current_attributes.set(Attribute::Hidden, false),
+                29 => self.current_attributes.set(Attribute::Strikethrough, false),
+                code @ 30..=37 => self.set_fg_color(Color::Indexed((code - 30) as u8)),
+                38 => {
+                    if let Some(color) = parse_extended_color(group, &mut groups) {
+                        self.set_fg_color(color);
+                    }
+                }
+                39 => self.set_fg_color(Color::DefaultForeground),
+                code @ 40..=47 => self.set_bg_color(Color::Indexed((code - 40) as u8)),
+                48 => {
+                    if let Some(color) = parse_extended_color(group, &mut groups) {
+                        self.set_bg_color(color);
+                    }
+                }
+                49 => self.set_bg_color(Color::DefaultBackground),
+                53 => self.current_attributes.set(Attribute::Overline, true),
+                55 => self.current_attributes.set(Attribute::Overline, false),
+                58 => {
+                    if let Some(color) = parse_extended_color(group, &mut groups) {
+                        self.current_attributes.underline_color = Some(color);
+                    }
+                }
+                59 => self.current_attributes.underline_color = None,
+                code @ 90..=97 => self.set_fg_color(Color::Indexed((code - 90 + 8) as u8)),
+                code @ 100..=107 => self.set_bg_color(Color::Indexed((code - 100 + 8) as u8)),
+                _ => {}
+            }
+        }
+    }
+
+    /// Feeds raw bytes read from the PTY through the escape sequence parser.
+    pub fn process_output(&mut self, bytes: &[u8]) {
+        let mut parser = std::mem::take(&mut self.parser);
+        parser.advance(self, bytes);
+        self.parser = parser;
+        self.collect_interned();
+
+        if self.scroll_on_output {
+            self.scroll_to_bottom();
+        }
+        self.prepare_viewport();
+    }
+
+    /// Frees interned graphemes and hyperlinks that no cell refers to anymore, once either
+    /// interner has grown enough since the last time. Compressed history is read too, as
+    /// its cells keep their ids.
+    fn collect_interned(&mut self) {
+        if !self.graphemes.needs_collection() && !self.hyperlinks.needs_collection() {
+            return;
+        }
+        let mut graphemes = vec![false; self.graphemes.max_id() as usize + 1];
+        let mut hyperlinks = vec![false; self.hyperlinks.max_id() as usize + 1];
+        hyperlinks[self.current_hyperlink as usize] = true;
+        for grid in [&self.grid, &self.inactive_grid] {
+            grid.for_each_row_from(0, |row| {
+                for cell in row.iter() {
+                    if let Some(live) = graphemes.get_mut(cell.grapheme as usize) {
+                        *live = true;
+                    }
+                    if let Some(live) = hyperlinks.get_mut(cell.hyperlink as usize) {
+                        *live = true;
+                    }
+                }
+            });
+        }
+        self.graphemes.retain(&graphemes);
+        self.hyperlinks.retain(&hyperlinks);
+    }
+
+    /// Moves the viewport by `lines`; positive values scroll back into history.
+    /// The alternate screen has no scrollback, so this does nothing there.
+    pub fn scroll_viewport(&mut self, lines: isize) {
+        if self.alternate_screen_active {
+            return;
+        }
+        self.scroll_offset = self
+            .scroll_offset
+            .saturating_add_signed(lines)
+            .min(self.history_len());
+        self.prepare_viewport();
+    }
+
+    pub fn scroll_to_top(&mut self) {
+        if !self.alternate_screen_active {
+            self.scroll_offset = self.history_len();
+            self.prepare_viewport();
+        }
+    }
+
+    pub fn scroll_to_bottom(&mut self) {
+        self.scroll_offset = 0;
+    }
+
+    /// The id of the top row in view; see `Grid::first_row_id`.
+    pub fn viewport_top_row(&self) -> u64 {
+        let top = self.grid.history_len() - self.viewport_offset();
+        self.grid.first_row_id() + top as u64
+    }
+
+    /// Scrolls the row with id `row` into view, centring it unless it is already within
+    /// the top `visible` rows of the viewport.
+    pub fn scroll_to_row(&mut self, row: u64, visible: usize) {
+        let top = self.viewport_top_row();
+        if self.alternate_screen_active || (top..top + visible as u64).contains(&row) {
+            return;
+        }
+        let bottom = self.grid.first_row_id() + self.history_len() as u64;
+        let centred_top = row.saturating_sub(visible as u64 / 2);
+        self.scroll_offset = (bottom.saturating_sub(centred_top) as usize).min(self.history_len());
+        self.prepare_viewport();
+    }
+
+    /// The rows currently in view: the tail of the history followed by the live screen,
+    /// shifted up by `scroll_offset`.
+    pub fn get_visible_cells(&self) -> Vec<&[TerminalCell]> {
+        self.grid
+            .viewport(self.viewport_offset())
+            .map(Row::deref)
+            .collect()
+    }
+
+    /// Decompresses any scrollback the viewport now shows.
+    fn prepare_viewport(&mut self) {
+        let offset = self.viewport_offset();
+        self.grid.prepare_viewport(offset);
+    }
+
+    /// Sets how many rows of scrollback are kept and how many bytes of compressed
+    /// scrollback stay in memory before older blocks move to a temporary file.
+    pub fn set_scrollback_limits(&mut self, lines: usize, memory_bytes: usize) {
+        self.primary_grid_mut().set_limits(lines, memory_bytes);
+        self.scroll_offset = self.scroll_offset.min(self.history_len());
+        self.prepare_viewport();
+    }
+
+    /// Sets the largest OSC 52 payload, in decoded bytes, that is written to the clipboard.
+    /// The parser keeps OSC strings long enough to hold one that size.
+    pub fn set_clipboard_max_bytes(&mut self, bytes: usize) {
+        self.clipboard_max_bytes = bytes;
+        let encoded = bytes.div_ceil(3) * 4;
+        self.parser
+            .set_max_osc_length(MAX_OSC_LENGTH.max(encoded + OSC52_PARAMS_LENGTH));
+    }
+
+    /// The grid holding the primary screen and the scrollback, whichever screen is active.
+    fn primary_grid_mut(&mut self) -> &mut Grid {
+        if self.alternate_screen_active {
+            &mut self.inactive_grid
+        } else {
+            &mut self.grid
+        }
+    }
+
+    /// How many rows of scrollback the primary screen has.
+    pub fn history_len(&self) -> usize {
+        if self.alternate_screen_active {
+            self.inactive_grid.history_len()
+        } else {
+            self.grid.history_len()
+        }
+    }
+
+    /// How far the live screen is pushed down the viewport by scrollback.
+    fn viewport_offset(&self) -> usize {
+        if self.alternate_screen_active {
+            0
+        } else {
+            self.scroll_offset
+        }
+    }
+
+    /// The cursor's row within the viewport, or `None` when it is scrolled out of view.
+    pub fn cursor_viewport_row(&self) -> Option<usize> {
+        let row = self.cursor_y + self.viewport_offset();
+        (row < self.height).then_some(row)
+    }
+
+    /// XTWINOPS 22: saves the title and icon name. The oldest entry is dropped when full.
+    pub fn push_title(&mut self) {
+        if self.title_stack.len() == MAX_TITLE_STACK {
+            self.title_stack.remove(0);
+        }
+        self.title_stack
+            .push((self.title.clone(), self.icon_name.clone()));
+    }
+
+    /// XTWINOPS 23: restores the icon name (`which` 1), the title (2) or both (0).
+    pub fn pop_title(&mut self, which: u16) {
+        let Some((title, icon_name)) = self.title_stack.pop() else {
+            return;
+        };
+        if which != 1 {
+            self.title = title;
+        }
+        if which != 2 {
+            self.icon_name = icon_name;
+        }
+    }
+
+    /// Takes the replies produced since the last call, to be written to the PTY.
+    pub fn take_responses(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.responses)
+    }
+
+    fn respond(&mut self, reply: &str) {
+        self.responses.extend_from_slice(reply.as_bytes());
+    }
+
+    /// DA1: a VT220 with ANSI color.
+    fn report_primary_attributes(&mut self) {
+        self.respond("\x1b[?62;22c");
+    }
+
+    /// DA2: terminal type 1 (VT220) and the crate version as `MMmmpp`.
+    fn report_secondary_attributes(&mut self) {
+        let version = env!("CARGO_PKG_VERSION")
+            .split('.')
+            .take(3)
+            .fold(0, |acc, part| acc * 100 + part.parse::<u32>().unwrap_or(0));
+        self.respond(&format!("\x1b[>1;{};0c", version));
+    }
+
+    /// DSR: 5 reports status, 6 the cursor position, relative to the margins in origin mode.
+    fn report_status(&mut self, request: u16, private: bool) {
+        match request {
+            5 if !private => self.respond("\x1b[0n"),
+            6 => {
+                let row = if self.modes.origin {
+                    self.cursor_y.saturating_sub(self.scroll_top)
+                } else {
+                    self.cursor_y
+                };
+                let marker = if private { "?" } else { "" };
+                let reply = format!("\x1b[{}{};{}R", marker, row + 1, self.cursor_x + 1);
+                self.respond(&reply);
+            }
+            _ => {}
+        }
+    }
+
+    /// DECRQM: answers whether a mode is set (1), reset (2), permanently set (3),
+    /// permanently reset (4) or unknown (0).
+    fn report_mode(&mut self, mode: u16, private: bool) {
+        let state = if private {
+            self.private_mode_state(mode)
+        } else {
+            match mode {
+                4 => mode_flag(self.modes.insert),
+                20 => mode_flag(self.modes.newline),
+                _ => 0,
+            }
+        };
+        let marker = if private { "?" } else { "" };
+        self.respond(&format!("\x1b[{}{};{}$y", marker, mode, state));
+    }
+
+    fn private_mode_state(&self, mode: u16) -> u8 {
+        match mode {
+            1 => mode_flag(self.modes.application_cursor_keys),
+            5 => mode_flag(self.modes.reverse_video),
+            6 => mode_flag(self.modes.origin),
+            7 => mode_flag(self.modes.autowrap),
+            12 => mode_flag(self.cursor_style.blinking),
+            25 => mode_flag(self.modes.show_cursor),
+            9 => mode_flag(self.mouse_tracking == MouseTracking::X10),
+            1000 => mode_flag(self.mouse_tracking == MouseTracking::Normal),
+            1002 => mode_flag(self.mouse_tracking == MouseTracking::ButtonEvent),
+            1003 => mode_flag(self.mouse_tracking == MouseTracking::AnyEvent),
+            1004 => mode_flag(self.modes.focus_reporting),
+            1006 => mode_flag(self.mouse_encoding == MouseEncoding::Sgr),
+            1016 => mode_flag(self.mouse_encoding == MouseEncoding::SgrPixels),
+            47 | 1047 | 1049 => mode_flag(self.alternate_screen_active),
+            2004 => mode_flag(self.modes.bracketed_paste),
+            _ => 0,
+        }
+    }
+
+    /// DECRQSS: reports the current SGR or scrolling region as the sequence that sets it.
+    fn report_setting(&mut self, setting: &[u8]) {
+        let value = match setting {
+            b"m" => Some(format!("{}m", self.sgr_parameters())),
+            b"r" => Some(format!(
+                "{};{}r",
+                self.scroll_top + 1,
+                self.scroll_bottom + 1
+            )),
+            b" q" => Some(format!("{} q", self.cursor_style.decscusr())),
+            _ => None,
+        };
+        match value {
+            Some(value) => self.respond(&format!("\x1bP1$r{}\x1b\\", value)),
+            None => self.respond("\x1bP0$r\x1b\\"),
+        }
+    }
+
+    /// The SGR parameters that reproduce the current pen, starting from a reset.
+    fn sgr_parameters(&self) -> String {
+        let attributes = &self.current_attributes;
+        let mut parameters = vec!["0".to_string()];
+        let flags = [
+            (attributes.has(Attribute::Bold), "1"),
+            (attributes.has(Attribute::Dim), "2"),
+            (attributes.has(Attribute::Italic), "3"),
+            (attributes.has(Attribute::Blink), "5"),
+            (attributes.has(Attribute::Inverse), "7"),
+            (attributes.has(Attribute::Hidden), "8"),
+            (attributes.has(Attribute::Strikethrough), "9"),
+            (attributes.has(Attribute::Overline), "53"),
+        ];
+        parameters.extend(
+            flags
+                .iter()
+                .filter(|(set, _)| *set)
+                .map(|(_, code)| code.to_string()),
+        );
+        let underline = match attributes.underline() {
+            UnderlineStyle::None => None,
+            UnderlineStyle::Single => Some("4"),
+            UnderlineStyle::Double => Some("4:2"),
+            UnderlineStyle::Curly => Some("4:3"),
+            UnderlineStyle::Dotted => Some("4:4"),
+            UnderlineStyle::Dashed => Some("4:5"),
+        };
+        parameters.extend(underline.map(str::to_string));
+
+        let color = |color: Color, base: u16| match color {
+            Color::DefaultForeground | Color::DefaultBackground => None,
+            Color::Indexed(index) if index < 8 && base != 58 => {
+                Some(format!("{}", base - 8 + index as u16))
+            }
+            Color::Indexed(index) if index < 16 && base != 58 => {
+                Some(format!("{}", base + 52 + (index - 8) as u16))
+            }
+            Color::Indexed(index) => Some(format!("{}:5:{}", base, index)),
+            Color::Rgb(r, g, b) => Some(format!("{}:2::{}:{}:{}", base, r, g, b)),
+        };
+        parameters.extend(color(self.current_fg_color, 38));
+        parameters.extend(color(self.current_bg_color, 48));
+        parameters.extend(attributes.underline_color.and_then(|c| color(c, 58)));
+        parameters.join(";")
+    }
+
+    /// XTGETTCAP: looks up each hex-encoded capability name in the terminfo table. Known
+    /// names are answered together; an unknown name fails the whole request.
+    fn report_termcap(&mut self, names: &[u8]) {
+        let mut answers = Vec::new();
+        for hex_name in names.split(|&byte| byte == b';') {
+            let value = hex_decode(hex_name)
+                .and_then(|name| String::from_utf8(name).ok())
+                .and_then(|name| terminfo::lookup(&name));
+            let Some(value) = value else {
+                let name = String::from_utf8_lossy(hex_name);
+                self.respond(&format!("\x1bP0+r{}\x1b\\", name));
+                return;
+            };
+            let name = String::from_utf8_lossy(hex_name);
+            if value.is_empty() {
+                answers.push(name.into_owned());
+            } else {
+                answers.push(format!("{}={}", name, hex_encode(value.as_bytes())));
+            }
+        }
+        self.respond(&format!("\x1bP1+r{}\x1b\\", answers.join(";")));
+    }
+
+    /// Queues an OSC reply, ended the same way as the query it answers.
+    fn respond_osc(&mut self, body: &str, bell_terminated: bool) {
+        let terminator = if bell_terminated { "\x07" } else { "\x1b\\" };
+        self.responses
+            .extend_from_slice(format!("\x1b]{}{}", body, terminator).as_bytes());
+    }
+
+    /// OSC 4: pairs of palette index and color spec; `?` queries the entry.
+    fn set_indexed_colors(&mut self, params: &[&[u8]], bell_terminated: bool) {
+        for pair in params.chunks_exact(2) {
+            let Some(index) = parse_osc_number(pair[0]).and_then(|n| u8::try_from(n).ok()) else {
+                continue;
+            };
+            if pair[1] == b"?" {
+                let color = palette::format_color_spec(self.palette.colors[index as usize]);
+                self.respond_osc(&format!("4;{};{}", index, color), bell_terminated);
+            } else if let Some(color) = palette::parse_color_spec(pair[1]) {
+                self.palette.colors[index as usize] = color;
+            }
+        }
+    }
+
+    /// OSC 10/11/12 set the foreground, background and cursor colors. Further specs
+    /// continue with the next color, so `OSC 10;fg;bg` sets both.
+    fn set_dynamic_colors(&mut self, first: u16, specs: &[&[u8]], bell_terminated: bool) {
+        for (code, spec) in (first..=12).zip(specs) {
+            if *spec == b"?" {
+                let color = match code {
+                    10 => self.palette.foreground,
+                    11 => self.palette.background,
+                    _ => self.palette.cursor_color(),
+                };
+                let body = format!("{};{}", code, palette::format_color_spec(color));
+                self.respond_osc(&body, bell_terminated);
+                continue;
+            }
+
+            let Some(color) = palette::parse_color_spec(spec) else {
+                continue;
+            };
+            match code {
+                10 => self.palette.foreground = color,
+                11 => self.palette.background = color,
+                _ => self.palette.cursor = Some(color),
+            }
+        }
+    }
+
+    /// OSC 104: resets the listed palette entries, or all of them without parameters.
+    fn reset_indexed_colors(&mut self, params: &[&[u8]]) {
+        let indices: Vec<u8> = params
+            .iter()
+            .filter_map(|param| parse_osc_number(param).and_then(|n| u8::try_from(n).ok()))
+            .collect();
+        if params.iter().all(|param| param.is_empty()) {
+            for index in 0..=255 {
+                self.palette.reset_indexed(index);
+            }
+        }
+        for index in indices {
+            self.palette.reset_indexed(index);
+        }
+    }
+
+    /// OSC 52: `selection` lists the targets (`c` clipboard, `p`/`s` primary, empty means
+    /// the clipboard) and `data` is base64 text, or `?` to read.
+    fn handle_clipboard(
+        &mut self,
+        selection: &[u8],
+        data: &[u8],
+        bell_terminated: bool,
+        truncated: bool,
+    ) {
+        let mut targets = Vec::new();
+        for &byte in selection {
+            let target = match byte {
+                b'c' => ClipboardTarget::Clipboard,
+                b'p' | b's' => ClipboardTarget::Primary,
+                _ => continue,
+            };
+            if !targets.contains(&target) {
+                targets.push(target);
+            }
+        }
+        if selection.is_empty() {
+            targets.push(ClipboardTarget::Clipboard);
+        }
+        let Some(&first_target) = targets.first() else {
+            return;
+        };
+
+        if data == b"?" {
+            self.clipboard_requests.push(ClipboardRequest::Load {
+                target: first_target,
+                bell_terminated,
+            });
+            return;
+        }
+
+        // Check the size before decoding, so an oversized payload costs nothing. Padding
+        // isn't data, and a payload the parser cut short was too long for it.
+        let padding = data.iter().rev().take_while(|&&byte| byte == b'=').count();
+        let length = (data.len() - padding.min(2)) * 3 / 4;
+        if truncated || length > self.clipboard_max_bytes {
+            self.clipboard_requests.push(ClipboardRequest::Oversized);
+            return;
+        }
+        let Ok(decoded) = OSC52_BASE64.decode(data) else {
+            return;
+        };
+        let text = String::from_utf8_lossy(&decoded).into_owned();
+        for target in targets {
+            self.clipboard_requests.push(ClipboardRequest::Store {
+                target,
+                text: text.clone(),
+            });
+        }
+    }
+
+    /// The URI of the cell's hyperlink, if it has one.
+    pub fn hyperlink_uri(&self, cell: &TerminalCell) -> Option<&str> {
+        self.hyperlinks
+            .get(cell.hyperlink)
+            .map(|link| link.uri.as_str())
+    }
+
+    /// OSC 8: `params` is a `:`-separated list of `key=value` pairs, of which only `id`
+    /// is used. An empty URI ends the link.
+    fn set_hyperlink(&mut self, params: &[u8], uri: &[u8]) {
+        if uri.is_empty() {
+            self.current_hyperlink = 0;
+            return;
+        }
+
+        let id = params
+            .split(|&byte| byte == b':')
+            .find_map(|param| param.strip_prefix(b"id="))
+            .filter(|id| !id.is_empty())
+            .map(|id| String::from_utf8_lossy(id).into_owned());
+        let uri = String::from_utf8_lossy(uri).into_owned();
+        self.current_hyperlink = self.hyperlinks.intern(Hyperlink { id, uri });
+    }
+
+    /// The color a cell's underline is drawn with: SGR 58 if set, otherwise the text color.
+    pub fn underline_color(&self, cell: &TerminalCell, fg: TerminalColor) -> TerminalColor {
+        cell.attributes
+            .underline_color
+            .map_or(fg, |color| self.palette.resolve(color))
+    }
+
+    /// The color behind cells with the default background: the palette background, or the
+    /// foreground in reverse-video mode.
+    pub fn screen_background(&self) -> TerminalColor {
+        if self.modes.reverse_video {
+            self.palette.foreground
+        } else {
+            self.palette.background
+        }
+    }
+
+    /// The foreground and background a cell is drawn with, after inverse, reverse video and
+    /// dim are applied.
+    pub fn cell_colors(&self, cell: &TerminalCell) -> (TerminalColor, TerminalColor) {
+        let mut fg = self.palette.resolve(cell.fg_color);
+        let mut bg = self.palette.resolve(cell.bg_color);
+
+        if cell.attributes.has(Attribute::Inverse) != self.modes.reverse_video {
+            std::mem::swap(&mut fg, &mut bg);
+        }
+        if cell.attributes.has(Attribute::Dim) {
+            fg = TerminalColor::new(
+                (fg.r + bg.r) / 2.0,
+                (fg.g + bg.g) / 2.0,
+                (fg.b + bg.b) / 2.0,
+                fg.a,
+            );
+        }
+
+        (fg, bg)
+    }
+
+    pub fn get_cursor_position(&self) -> (usize, usize) {
+        (self.cursor_x, self.cursor_y)
+    }
+}
+
+fn parse_osc_number(param: &[u8]) -> Option<u16> {
+    std::str::from_utf8(param).ok()?.parse().ok()
+}
+
+/// Decodes an OSC title, dropping control characters and capping its length.
+fn sanitize_title(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes)
+        .chars()
+        .filter(|ch| !ch.is_control())
+        .take(MAX_TITLE_LENGTH)
+        .collect()
+}
+
+fn is_regional_indicator(ch: char) -> bool {
+    ('\u{1f1e6}'..='\u{1f1ff}').contains(&ch)
+}
+
+/// A tab stop every 8 columns, the power-on default.
+fn default_tab_stops(width: usize) -> Vec<bool> {
+    (0..width).map(|column| column % 8 == 0).collect()
+}
+
+/// A DECRQM answer for a mode that can be changed: 1 when set, 2 when reset.
+fn mode_flag(set: bool) -> u8 {
+    if set { 1 } else { 2 }
+}
+
+fn hex_decode(hex: &[u8]) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    hex.chunks(2)
+        .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok())
+        .collect()
+}
+
+fn hex_encode(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02X}", byte)).collect()
+}
+
+/// Joins soft-wrapped rows back into lines and wraps them again at a new width, following
+/// an anchor cell through the move.
+struct Rewrap {
+    width: usize,
+    /// The line so far, while its rows end in a soft wrap.
+    line: Vec<TerminalCell>,
+    /// Where in `line` the anchor is, once its row has been added.
+    anchor_offset: Option<usize>,
+    /// The anchor's (column, row) in `rows`, once its line has been wrapped.
+    anchor: Option<(usize, usize)>,
+    rows: Vec<Row>,
+}
+
+impl Rewrap {
+    fn new(width: usize) -> Self {
+        Self {
+            width,
+            line: Vec::new(),
+            anchor_offset: None,
+            anchor: None,
+            rows: Vec::new(),
+        }
+    }
+
+    /// Adds the next row, which holds the anchor at column `anchor_x` if given.
+    fn push(&mut self, row: &Row, anchor_x: Option<usize>) {
+        if let Some(x) = anchor_x {
+            self.anchor_offset = Some(self.line.len() + x);
+        }
+        self.line.extend(
+            row.iter()
+                .filter(|cell| cell.width != CellWidth::LeadingWideSpacer),
+        );
+        if row.wrapped {
+            return;
+        }
+
+        while self.line.last().is_some_and(TerminalCell::is_blank) {
+            self.line.pop();
+        }
+        let offset = self.anchor_offset.take();
+        let (rows, position) = wrap_line(std::mem::take(&mut self.line), self.width, offset);
+        if let Some((x, y)) = position {
+            self.anchor = Some((x, self.rows.len() + y));
+        }
+        self.rows.extend(rows);
+    }
+}
+
+/// Splits a logical line into rows of `width` cells, moving a wide character that would
+/// straddle the right edge onto the next row. Also returns the (column, row) that cell
+/// `offset` of the line ends up at; offsets past the end count blank columns.
+fn wrap_line(
+    line: Vec<TerminalCell>,
+    width: usize,
+    offset: Option<usize>,
+) -> (Vec<Row>, Option<(usize, usize)>) {
+    let line_length = line.len();
+    let mut rows = Vec::new();
+    let mut current: Vec<TerminalCell> = Vec::with_capacity(width);
+    let mut position = None;
+
+    for (index, cell) in line.into_iter().enumerate() {
+        let columns = if cell.width == CellWidth::Wide { 2 } else { 1 };
+        if current.len() + columns > width && !current.is_empty() {
+            current.resize(
+                width,
+                TerminalCell {
+                    width: CellWidth::LeadingWideSpacer,
+                    ..TerminalCell::default()
+                },
+            );
+            rows.push(Row {
+                cells: std::mem::replace(&mut current, Vec::with_capacity(width)),
+                wrapped: true,
+            });
+        }
+        if Some(index) == offset {
+            position = Some((current.len(), rows.len()));
+        }
+        current.push(cell);
+    }
+
+    if position.is_none()
+        && let Some(offset) = offset
+    {
+        let column = current.len() + offset.saturating_sub(line_length);
+        position = Some((column % width, rows.len() + column / width));
+    }
+
+    current.resize(width, TerminalCell::default());
+    rows.push(Row {
+        cells: current,
+        wrapped: false,
+    });
+    if let Some((_, row)) = position {
+        while rows.len() <= row {
+            rows.push(Row::new(width));
+        }
+    }
+
+    (rows, position)
+}
+
+/// Parses the color following SGR 38/48/58. `group` holds the 38/48 itself plus any colon
+/// sub-parameters; without sub-parameters the color is read from the following groups.
+fn parse_extended_color(group: &[u16], groups: &mut ParamsIter) -> Option<Color> {
+    if group.len() > 1 {
+        return match group[1] {
+            5 => group
+                .get(2)
+                .and_then(|&index| u8::try_from(index).ok())
+                .map(Color::Indexed),
+            // ITU T.416 puts a color space id before the components; many programs omit it
+            2 if group.len() >= 6 => Some(rgb_color(group[3], group[4], group[5])),
+            2 if group.len() == 5 => Some(rgb_color(group[2], group[3], group[4])),
+            _ => None,
+        };
+    }
+
+    match groups.next()?[0] {
+        5 => u8::try_from(groups.next()?[0]).ok().map(Color::Indexed),
+        2 => {
+            let r = groups.next()?[0];
+            let g = groups.next()?[0];
+            let b = groups.next()?[0];
+            Some(rgb_color(r, g, b))
+        }
+        _ => None,
+    }
+}
+
+fn rgb_color(r: u16, g: u16, b: u16) -> Color {
+    Color::Rgb(r.min(255) as u8, g.min(255) as u8, b.min(255) as u8)
+}
+
+/// A count parameter where both an omitted value and 0 mean 1.
+fn count_param(params: &Params, index: usize) -> usize {
+    match params.get(index) {
+        Some(0) | None => 1,
+        Some(value) => value as usize,
+    }
+}
+
+impl Perform for TerminalState {
+    fn print(&mut self, ch: char) {
+        let ch = self.charsets.translate(ch);
+        self.put_char(ch);
+    }
+
+    fn execute(&mut self, byte: u8) {
+        match byte {
+            0x08 => {
+                self.cursor_x = self.cursor_x.saturating_sub(1);
+                self.wrap_pending = false;
+            }
+            0x09 => self.tab_forward(1),
+            0x0a..=0x0c if self.modes.newline => self.newline(),
+            0x0a..=0x0c => self.linefeed(),
+            0x0d => {
+                self.cursor_x = 0;
+                self.wrap_pending = false;
+            }
+            0x0e => self.charsets.active = 1,
+            0x0f => self.charsets.active = 0,
+            _ => {}
+        }
+    }
+
+    fn csi_dispatch(&mut self, params: &Params, intermediates: &[u8], ignore: bool, action: char) {
+        if ignore {
+            return;
+        }
+
+        match (intermediates, action) {
+            ([], _) => {}
+            ([b'?'], 'h' | 'l') => {
+                for mode in params.iter() {
+                    self.set_private_mode(mode[0], action == 'h');
+                }
+                return;
+            }
+            ([b'!'], 'p') => {
+                self.soft_reset();
+                return;
+            }
+            ([b'?'], 'n') => {
+                self.report_status(params.get(0).unwrap_or(0), true);
+                return;
+            }
+            ([b'$'], 'p') | ([b'?', b'$'], 'p') => {
+                let private = intermediates[0] == b'?';
+                self.report_mode(params.get(0).unwrap_or(0), private);
+                return;
+            }
+            ([b'>'], 'c') => {
+                if params.get(0).unwrap_or(0) == 0 {
+                    self.report_secondary_attributes();
+                }
+                return;
+            }
+            ([b'='], 'c') => {
+                if params.get(0).unwrap_or(0) == 0 {
+                    // DA3: the unit ID, which is all zeros like xterm's
+                    self.respond("\x1bP!|00000000\x1b\\");
+                }
+                return;
+            }
+            ([b' '], 'q') => {
+                let value = params.get(0).unwrap_or(0);
+                if let Some(style) = CursorStyle::from_decscusr(value, self.default_cursor_style) {
+                    self.cursor_style = style;
+                }
+                return;
+            }
+            ([b'>'], 'q') => {
+                if params.get(0).unwrap_or(0) == 0 {
+                    let name = env!("CARGO_PKG_NAME");
+                    let version = env!("CARGO_PKG_VERSION");
+                    self.respond(&format!("\x1bP>|{}({})\x1b\\", name, version));
+                }
+                return;
+            }
+            _ => return,
+        }
+
+        match action {
+            'A' => self.move_cursor_up(count_param(params, 0)),
+            'B' | 'e' => self.move_cursor_down(count_param(params, 0)),
+            'C' | 'a' => self.move_cursor_by(count_param(params, 0) as isize),
+            'D' => self.move_cursor_by(-(count_param(params, 0) as isize)),
+            'E' => {
+                self.move_cursor_down(count_param(params, 0));
+                self.cursor_x = 0;
+            }
+            'F' => {
+                self.move_cursor_up(count_param(params, 0));
+                self.cursor_x = 0;
+            }
+            'G' | '`' => self.move_cursor_to(count_param(params, 0) - 1, self.cursor_y),
+            'd' => {
+                let y = count_param(params, 0) - 1;
+                self.set_cursor_position(self.cursor_x, y);
+            }
+            'H' | 'f' => {
+                self.set_cursor_position(count_param(params, 1) - 1, count_param(params, 0) - 1)
+            }
+            'J' => self.erase_in_display(params.get(0).unwrap_or(0)),
+            'K' => self.erase_in_line(params.get(0).unwrap_or(0)),
+            'X' => self.erase_chars(count_param(params, 0)),
+            'I' => self.tab_forward(count_param(params, 0)),
+            'Z' => self.tab_backward(count_param(params, 0)),
+            'g' => self.clear_tab_stops(params.get(0).unwrap_or(0)),
+            '@' => self.insert_chars(count_param(params, 0)),
+            'P' => self.delete_chars(count_param(params, 0)),
+            'L' => self.insert_lines(count_param(params, 0)),
+            'M' => self.delete_lines(count_param(params, 0)),
+            'S' => self.scroll_up(count_param(params, 0)),
+            'T' => self.scroll_down(count_param(params, 0)),
+            'r' => self.set_scrolling_region(
+                params.get(0).unwrap_or(0) as usize,
+                params.get(1).unwrap_or(0) as usize,
+            ),
+            'm' => self.set_graphics_rendition(params),
+            'h' | 'l' => {
+                for mode in params.iter() {
+                    self.set_mode(mode[0], action == 'h');
+                }
+            }
+            's' => self.save_cursor(),
+            'u' => self.restore_cursor(),
+            'c' if params.get(0).unwrap_or(0) == 0 => self.report_primary_attributes(),
+            'n' => self.report_status(params.get(0).unwrap_or(0), false),
+            't' => match params.get(0) {
+                Some(22) => self.push_title(),
+                Some(23) => self.pop_title(params.get(1).unwrap_or(0)),
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+
+    fn esc_dispatch(&mut self, intermediates: &[u8], ignore: bool, byte: u8) {
+        if ignore {
+            return;
+        }
+
+        match intermediates {
+            [] => {}
+            [slot @ (b'(' | b')' | b'*' | b'+')] => {
+                if let Some(charset) = Charset::from_designator(byte) {
+                    self.charsets.slots[(slot - b'(') as usize] = charset;
+                }
+                return;
+            }
+            _ => return,
+        }
+
+        match byte {
+            b'D' => self.linefeed(),
+            b'E' => self.newline(),
+            b'M' => self.reverse_index(),
+            b'7' => self.save_cursor(),
+            b'8' => self.restore_cursor(),
+            b'H' => self.tab_stops[self.cursor_x] = true,
+            b'c' => self.full_reset(),
+            b'N' => self.charsets.single_shift = Some(2),
+            b'O' => self.charsets.single_shift = Some(3),
+            b'n' => self.charsets.active = 2,
+            b'o' => self.charsets.active = 3,
+            _ => {}
+        }
+    }
+
+    fn osc_dispatch(&mut self, params: &[&[u8]], bell_terminated: bool, truncated: bool) {
+        // Only a clipboard write is expected to be this long; anything else is dropped
+        if truncated {
+            if let [b"52", selection, data] = params {
+                self.handle_clipboard(selection, data, bell_terminated, true);
+            }
+            return;
+        }
+        let Some(code) = params.first().and_then(|code| parse_osc_number(code)) else {
+            return;
+        };
+        if matches!(code, 4 | 10..=12 | 104 | 110..=112) {
+            self.full_damage = true;
+        }
+        match code {
+            4 => self.set_indexed_colors(&params[1..], bell_terminated),
+            10..=12 => self.set_dynamic_colors(code, &params[1..], bell_terminated),
+            104 => self.reset_indexed_colors(&params[1..]),
+            110 => self.palette.reset_foreground(),
+            111 => self.palette.reset_background(),
+            112 => self.palette.reset_cursor(),
+            _ => {}
+        }
+
+        match params {
+            [kind @ (b"0" | b"1" | b"2"), text @ ..] if !text.is_empty() => {
+                // The text may itself contain `;`, which the parser split on
+                let text = sanitize_title(&text.join(&b';'));
+                if kind != b"2" {
+                    self.icon_name = text.clone();
+                }
+                if kind != b"1" {
+                    self.title = text;
+                }
+            }
+            [b"52", selection, data] => {
+                self.handle_clipboard(selection, data, bell_terminated, false)
+            }
+            // The URI may itself contain `;`, which the parser split on
+            [b"8", link_params, uri @ ..] if !uri.is_empty() => {
+                self.set_hyperlink(link_params, &uri.join(&b';'));
+            }
+            _ => {}
+        }
+    }
+
+    fn hook(&mut self, _params: &Params, intermediates: &[u8], ignore: bool, action: char) {
+        self.dcs = match (intermediates, action) {
+            _ if ignore => None,
+            ([b'$'], 'q') => Some(DcsRequest::Setting(Vec::new())),
+            ([b'+'], 'q') => Some(DcsRequest::Termcap(Vec::new())),
+            _ => None,
+        };
+    }
+
+    fn put(&mut self, byte: u8) {
+        let data = match &mut self.dcs {
+            Some(DcsRequest::Setting(data) | DcsRequest::Termcap(data)) => data,
+            None => return,
+        };
+        if data.len() < MAX_DCS_LENGTH {
+            data.push(byte);
+        } else {
+            self.dcs = None;
+        }
+    }
+
+    fn unhook(&mut self) {
+        match self.dcs.take() {
+            Some(DcsRequest::Setting(setting)) => self.report_setting(&setting),
+            Some(DcsRequest::Termcap(names)) => self.report_termcap(&names),
+            None => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Asks for the current SGR with DECRQSS and returns the parameters of the reply.
+    fn requested_sgr(terminal: &mut TerminalState) -> String {
+        terminal.process_output(b"\x1bP$qm\x1b\\");
+        let reply = String::from_utf8(terminal.take_responses()).unwrap();
+        reply
+            .strip_prefix("\x1bP1$r")
+            .and_then(|reply| reply.strip_suffix("m\x1b\\"))
+            .unwrap()
+            .to_string()
+    }
+
+    /// Writes `length` bytes to the clipboard with OSC 52 and returns the requests made.
+    fn write_clipboard(terminal: &mut TerminalState, length: usize) -> Vec<ClipboardRequest> {
+        let data = OSC52_BASE64.encode(vec![b'x'; length]);
+        terminal.process_output(format!("\x1b]52;c;{}\x07", data).as_bytes());
+        std::mem::take(&mut terminal.clipboard_requests)
+    }
+
+    #[test]
+    fn osc52_writes_up_to_clipboard_max_bytes() {
+        // One of each remainder by 3, so every amount of base64 padding is covered
+        for max in [1 << 20, 2 << 20, 3 << 20] {
+            let mut terminal = TerminalState::new(10, 3);
+            terminal.set_clipboard_max_bytes(max);
+            let requests = write_clipboard(&mut terminal, max);
+            assert!(
+                matches!(&requests[..], [ClipboardRequest::Store { text, .. }] if text.len() == max)
+            );
+            let requests = write_clipboard(&mut terminal, max + 1);
+            assert!(matches!(requests[..], [ClipboardRequest::Oversized]));
+        }
+    }
+
+    #[test]
+    fn osc52_write_cut_short_by_the_parser_is_oversized() {
+        let mut terminal = TerminalState::new(10, 3);
+        terminal.set_clipboard_max_bytes(4 << 20);
+        terminal.parser.set_max_osc_length(MAX_OSC_LENGTH);
+        let requests = write_clipboard(&mut terminal, 2 << 20);
+        assert!(matches!(requests[..], [ClipboardRequest::Oversized]));
+    }
+
+    #[test]
+    fn history_hyperlinks_and_graphemes_survive_ris() {
+        let mut terminal = TerminalState::new(10, 3);
+        terminal.process_output(
+            "\x1b]8;;https://good.example\x07e\u{301}\x1b]8;;\x07\r\n\n\n".as_bytes(),
+        );
+        assert_eq!(terminal.history_len(), 1);
+        terminal.process_output("\x1bc\x1b]8;;https://evil.example\x07x\u{302}".as_bytes());
+
+        let mut cell = None;
+        terminal.grid.for_each_row_from(0, |row| {
+            cell.get_or_insert(row[0]);
+        });
+        let cell = cell.unwrap();
+        assert_eq!(terminal.hyperlink_uri(&cell), Some("https://good.example"));
+        assert_eq!(terminal.cell_text(&cell), "e\u{301}");
+    }
+
+    #[test]
+    fn resize_rewraps_compressed_history() {
+        let mut terminal = TerminalState::new(20, 10);
+        terminal.set_scrollback_limits(10000, 1 << 20);
+        for line in 0..3000 {
+            terminal
+                .process_output(format!("{:05}-abcdefghijklmnopqrstuvwxyz\r\n", line).as_bytes());
+        }
+        assert!(terminal.grid.cold_len() > 0);
+        terminal.resize(40, 10);
+
+        let mut lines = Vec::new();
+        terminal.grid.for_each_row_from(0, |row| {
+            let text: String = row.iter().map(|cell| cell.character).collect();
+            lines.push((text.trim_end().to_string(), row.wrapped));
+        });
+        for (line, (text, wrapped)) in lines[..2990].iter().enumerate() {
+            assert_eq!(*text, format!("{:05}-abcdefghijklmnopqrstuvwxyz", line));
+            assert!(!wrapped);
+        }
+    }
+
+    #[test]
+    fn decrqss_sgr_round_trips_the_16_colors() {
+        for index in 0..16u8 {
+            let mut terminal = TerminalState::new(10, 3);
+            let sgr = format!("\x1b[38;5;{};48;5;{}m", index, index);
+            terminal.process_output(sgr.as_bytes());
+            let parameters = requested_sgr(&mut terminal);
+
+            let mut replayed = TerminalState::new(10, 3);
+            replayed.process_output(format!("\x1b[{}m", parameters).as_bytes());
+            assert_eq!(
+                replayed.current_fg_color,
+                Color::Indexed(index),
+                "{}",
+                parameters
+            );
+            assert_eq!(
+                replayed.current_bg_color,
+                Color::Indexed(index),
+                "{}",
+                parameters
+            );
+        }
+    }
+
+    #[test]
+    fn decrqss_sgr_reports_bright_colors_as_aixterm_codes() {
+        let mut terminal = TerminalState::new(10, 3);
+        terminal.process_output(b"\x1b[91;102m");
+        assert_eq!(requested_sgr(&mut terminal), "0;91;102");
     }
 }
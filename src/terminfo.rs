@@ -0,0 +1,130 @@
+/// The terminfo entry programs are told to use, matching `TERM` in the child's environment.
+pub const TERM_NAME: &str = "xterm-256color";
+
+/// Capabilities answered through XTGETTCAP, as (name, value) with escapes already expanded.
+/// Boolean capabilities have an empty value.
+const CAPABILITIES: &[(&str, &str)] = &[
+    ("TN", TERM_NAME),
+    ("name", TERM_NAME),
+    ("Co", "256"),
+    ("colors", "256"),
+    ("RGB", "8/8/8"),
+    ("Tc", ""),
+    ("bce", ""),
+    ("am", ""),
+    ("xenl", ""),
+    ("bel", "\x07"),
+    ("cr", "\r"),
+    ("ht", "\t"),
+    ("ind", "\n"),
+    ("ri", "\x1bM"),
+    ("nel", "\x1bE"),
+    ("hts", "\x1bH"),
+    ("tbc", "\x1b[3g"),
+    ("cbt", "\x1b[Z"),
+    ("clear", "\x1b[H\x1b[2J"),
+    ("ed", "\x1b[J"),
+    ("el", "\x1b[K"),
+    ("el1", "\x1b[1K"),
+    ("ech", "\x1b[%p1%dX"),
+    ("home", "\x1b[H"),
+    ("cup", "\x1b[%i%p1%d;%p2%dH"),
+    ("cuu1", "\x1b[A"),
+    ("cud1", "\n"),
+    ("cuf1", "\x1b[C"),
+    ("cub1", "\x08"),
+    ("cuu", "\x1b[%p1%dA"),
+    ("cud", "\x1b[%p1%dB"),
+    ("cuf", "\x1b[%p1%dC"),
+    ("cub", "\x1b[%p1%dD"),
+    ("hpa", "\x1b[%i%p1%dG"),
+    ("vpa", "\x1b[%i%p1%dd"),
+    ("csr", "\x1b[%i%p1%d;%p2%dr"),
+    ("ich", "\x1b[%p1%d@"),
+    ("dch", "\x1b[%p1%dP"),
+    ("dch1", "\x1b[P"),
+    ("il", "\x1b[%p1%dL"),
+    ("il1", "\x1b[L"),
+    ("dl", "\x1b[%p1%dM"),
+    ("dl1", "\x1b[M"),
+    ("indn", "\x1b[%p1%dS"),
+    ("rin", "\x1b[%p1%dT"),
+    ("sc", "\x1b7"),
+    ("civis", "\x1b[?25l"),
+    ("cnorm", "\x1b[?12l\x1b[?25h"),
+    ("cvvis", "\x1b[?12;25h"),
+    ("Ss", "\x1b[%p1%d q"),
+    ("Se", "\x1b[2 q"),
+    ("rc", "\x1b8"),
+    ("smir", "\x1b[4h"),
+    ("rmir", "\x1b[4l"),
+    ("smam", "\x1b[?7h"),
+    ("rmam", "\x1b[?7l"),
+    ("flash", "\x1b[?5h$<100/>\x1b[?5l"),
+    ("smkx", "\x1b[?1h"),
+    ("rmkx", "\x1b[?1l"),
+    ("kcuu1", "\x1bOA"),
+    ("kcud1", "\x1bOB"),
+    ("kcuf1", "\x1bOC"),
+    ("kcub1", "\x1bOD"),
+    ("khome", "\x1bOH"),
+    ("kend", "\x1bOF"),
+    ("smcup", "\x1b[?1049h"),
+    ("rmcup", "\x1b[?1049l"),
+    ("sgr0", "\x1b(B\x1b[m"),
+    ("bold", "\x1b[1m"),
+    ("dim", "\x1b[2m"),
+    ("sitm", "\x1b[3m"),
+    ("ritm", "\x1b[23m"),
+    ("smul", "\x1b[4m"),
+    ("rmul", "\x1b[24m"),
+    ("Smulx", "\x1b[4:%p1%dm"),
+    (
+        "Setulc",
+        "\x1b[58:2::%p1%{65536}%/%d:%p1%{256}%/%{255}%&%d:%p1%{255}%&%dm",
+    ),
+    ("blink", "\x1b[5m"),
+    ("rev", "\x1b[7m"),
+    ("smso", "\x1b[7m"),
+    ("rmso", "\x1b[27m"),
+    ("invis", "\x1b[8m"),
+    ("smxx", "\x1b[9m"),
+    ("rmxx", "\x1b[29m"),
+    ("smacs", "\x1b(0"),
+    ("rmacs", "\x1b(B"),
+    ("enacs", ""),
+    (
+        "acsc",
+        "``aaffggiijjkkllmmnnooppqqrrssttuuvvwwxxyyzz{{||}}~~",
+    ),
+    (
+        "setaf",
+        "\x1b[%?%p1%{8}%<%t3%p1%d%e%p1%{16}%<%t9%p1%{8}%-%d%e38;5;%p1%d%;m",
+    ),
+    (
+        "setab",
+        "\x1b[%?%p1%{8}%<%t4%p1%d%e%p1%{16}%<%t10%p1%{8}%-%d%e48;5;%p1%d%;m",
+    ),
+    ("setrgbf", "\x1b[38;2;%p1%d;%p2%d;%p3%dm"),
+    ("setrgbb", "\x1b[48;2;%p1%d;%p2%d;%p3%dm"),
+    ("op", "\x1b[39;49m"),
+    ("Ms", "\x1b]52;%p1%s;%p2%s\x07"),
+    ("tsl", "\x1b]2;"),
+    ("fsl", "\x07"),
+    ("hs", ""),
+    ("BE", "\x1b[?2004h"),
+    ("BD", "\x1b[?2004l"),
+    ("PS", "\x1b[200~"),
+    ("PE", "\x1b[201~"),
+    ("kmous", "\x1b[M"),
+    ("XM", "\x1b[?1006;1000%?%p1%{1}%=%th%el%;"),
+    ("kbs", "\x7f"),
+];
+
+/// Looks up a capability by its terminfo or termcap name.
+pub fn lookup(name: &str) -> Option<&'static str> {
+    CAPABILITIES
+        .iter()
+        .find(|(capability, _)| *capability == name)
+        .map(|(_, value)| *value)
+}
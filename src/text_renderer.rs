@@ -1,9 +1,11 @@
+use crate::terminal::UnderlineStyle;
 use ash::Device;
 use ash::vk;
 use bytemuck::{Pod, Zeroable};
 use fontdue::{Font, FontSettings};
 use std::collections::HashMap;
 use std::mem;
+use unicode_width::UnicodeWidthChar;
 
 #[repr(C)]
 #[derive(Clone, Copy, Debug, Pod, Zeroable)]
@@ -52,8 +54,34 @@ impl Vertex {
     }
 }
 
+/// Geometry built on the CPU and drawn with one call. Indices start at 0 for each batch.
+#[derive(Clone, Debug, Default)]
+pub struct Batch {
+    pub vertices: Vec<Vertex>,
+    pub indices: Vec<u32>,
+}
+
+impl Batch {
+    pub fn clear(&mut self) {
+        self.vertices.clear();
+        self.indices.clear();
+    }
+}
+
+/// The part of the vertex and index buffers reserved for one batch.
+#[derive(Clone, Copy, Debug)]
+struct BatchSlot {
+    first_vertex: usize,
+    vertex_capacity: usize,
+    first_index: usize,
+    index_capacity: usize,
+    index_count: usize,
+}
+
 pub struct GlyphInfo {
     pub texture_id: u32,
+    /// Top row of the glyph in the atlas.
+    pub texture_y: u32,
     pub width: u32,
     pub height: u32,
     pub bearing_x: i32,
@@ -83,10 +111,29 @@ pub struct TextRenderer {
     pub atlas_height: u32,
     pub atlas_x: u32,
     pub atlas_y: u32,
+    /// Height of the tallest glyph on the current atlas row.
+    pub atlas_row_height: u32,
     pub atlas_data: Vec<u8>,
     pub fixed_advance: f32,
+    pub line_height: f32,
+    pub ascent: f32,
+    /// Thickness of underlines, strikethrough and overline.
+    pub line_thickness: f32,
+    pub vertex_capacity: usize,
+    pub index_capacity: usize,
+    /// Where each batch from the last `upload_batches` lives in the buffers.
+    batch_slots: Vec<BatchSlot>,
 }
 
+const INITIAL_VERTEX_CAPACITY: usize = 1024;
+const INITIAL_INDEX_CAPACITY: usize = 6144;
+
+/// Smallest slot reserved for a batch, so rows that gain a few glyphs don't force a new layout.
+const MIN_BATCH_VERTICES: usize = 64;
+
+/// Size of the fully opaque block at the atlas origin, sampled for solid rectangles.
+const SOLID_BLOCK_SIZE: u32 = 4;
+
 impl TextRenderer {
     pub fn new(
         device: Device,
@@ -105,20 +152,37 @@ impl TextRenderer {
 
         let (metrics, _) = font.rasterize('M', font_size);
         let fixed_advance = metrics.advance_width;
+        let (line_height, ascent) = font
+            .horizontal_line_metrics(font_size)
+            .map(|line_metrics| {
+                (
+                    line_metrics.new_line_size.ceil(),
+                    line_metrics.ascent.round(),
+                )
+            })
+            .unwrap_or((font_size, font_size * 0.8));
 
         let descriptor_set_layout = Self::create_descriptor_set_layout(&device)?;
         let (graphics_pipeline, pipeline_layout) =
             Self::create_graphics_pipeline(&device, render_pass, extent, descriptor_set_layout)?;
 
-        let (vertex_buffer, vertex_buffer_memory) =
-            Self::create_vertex_buffer(&device, physical_device, instance)?;
+        let (vertex_buffer, vertex_buffer_memory) = Self::create_vertex_buffer(
+            &device,
+            physical_device,
+            instance,
+            INITIAL_VERTEX_CAPACITY,
+        )?;
 
         let (index_buffer, index_buffer_memory) =
-            Self::create_index_buffer(&device, physical_device, instance)?;
+            Self::create_index_buffer(&device, physical_device, instance, INITIAL_INDEX_CAPACITY)?;
 
         let atlas_width = 1024;
         let atlas_height = 1024;
-        let atlas_data = vec![0; (atlas_width * atlas_height) as usize];
+        let mut atlas_data = vec![0; (atlas_width * atlas_height) as usize];
+        for y in 0..SOLID_BLOCK_SIZE {
+            let row_start = (y * atlas_width) as usize;
+            atlas_data[row_start..row_start + SOLID_BLOCK_SIZE as usize].fill(255);
+        }
 
         let (texture_image, texture_image_memory) = Self::create_texture_image(
             &device,
@@ -145,6 +209,12 @@ impl TextRenderer {
             glyph_cache: HashMap::new(),
             font_size,
             fixed_advance,
+            line_height,
+            ascent,
+            line_thickness: (font_size / 14.0).round().max(1.0),
+            vertex_capacity: INITIAL_VERTEX_CAPACITY,
+            index_capacity: INITIAL_INDEX_CAPACITY,
+            batch_slots: Vec::new(),
             device,
             graphics_pipeline,
             pipeline_layout,
@@ -161,8 +231,9 @@ impl TextRenderer {
             descriptor_sets,
             atlas_width,
             atlas_height,
-            atlas_x: 0,
+            atlas_x: SOLID_BLOCK_SIZE,
             atlas_y: 0,
+            atlas_row_height: SOLID_BLOCK_SIZE,
             atlas_data,
         };
 
@@ -337,8 +408,9 @@ impl TextRenderer {
         device: &Device,
         physical_device: vk::PhysicalDevice,
         instance: &ash::Instance,
+        capacity: usize,
     ) -> Result<(vk::Buffer, vk::DeviceMemory), Box<dyn std::error::Error>> {
-        let buffer_size = (mem::size_of::<Vertex>() * 1024) as vk::DeviceSize;
+        let buffer_size = (mem::size_of::<Vertex>() * capacity) as vk::DeviceSize;
 
         let buffer_info = vk::BufferCreateInfo {
             size: buffer_size,
@@ -377,8 +449,9 @@ impl TextRenderer {
         device: &Device,
         physical_device: vk::PhysicalDevice,
         instance: &ash::Instance,
+        capacity: usize,
     ) -> Result<(vk::Buffer, vk::DeviceMemory), Box<dyn std::error::Error>> {
-        let buffer_size = (mem::size_of::<u16>() * 6144) as vk::DeviceSize;
+        let buffer_size = (mem::size_of::<u32>() * capacity) as vk::DeviceSize;
 
         let buffer_info = vk::BufferCreateInfo {
             size: buffer_size,
@@ -545,7 +618,6 @@ impl TextRenderer {
             image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
             image_view: texture_image_view,
             sampler: texture_sampler,
-            ..Default::default()
         };
 
         let descriptor_write = vk::WriteDescriptorSet {
@@ -647,7 +719,6 @@ impl TextRenderer {
                 height,
                 depth: 1,
             },
-            ..Default::default()
         };
 
         unsafe {
@@ -795,10 +866,11 @@ impl TextRenderer {
         Err("Failed to find suitable memory type".into())
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn render_text_to_buffer(
         &mut self,
         vertices: &mut Vec<Vertex>,
-        indices: &mut Vec<u16>,
+        indices: &mut Vec<u32>,
         text: &str,
         x: f32,
         y: f32,
@@ -818,54 +890,7 @@ impl TextRenderer {
             }
 
             if let Some(glyph_info) = self.glyph_cache.get(&ch) {
-                let x_pos = current_x + glyph_info.bearing_x as f32;
-                // Align to baseline using bearing_y (ymin) for consistent inline rendering
-                let y_pos = y - glyph_info.bearing_y as f32;
-
-                let w = glyph_info.width as f32;
-                let h = glyph_info.height as f32;
-
-                let u0 = glyph_info.texture_id as f32 / self.atlas_width as f32;
-                let v0 = self.atlas_y as f32 / self.atlas_height as f32;
-                let u1 =
-                    (glyph_info.texture_id + glyph_info.width) as f32 / self.atlas_width as f32;
-                let v1 =
-                    (self.atlas_y as f32 + glyph_info.height as f32) / self.atlas_height as f32;
-
-                let index_offset = vertices.len() as u16;
-
-                vertices.extend_from_slice(&[
-                    Vertex {
-                        position: [x_pos, y_pos + h],
-                        tex_coord: [u0, v1],
-                        color,
-                    },
-                    Vertex {
-                        position: [x_pos, y_pos],
-                        tex_coord: [u0, v0],
-                        color,
-                    },
-                    Vertex {
-                        position: [x_pos + w, y_pos],
-                        tex_coord: [u1, v0],
-                        color,
-                    },
-                    Vertex {
-                        position: [x_pos + w, y_pos + h],
-                        tex_coord: [u1, v1],
-                        color,
-                    },
-                ]);
-
-                indices.extend_from_slice(&[
-                    index_offset,
-                    index_offset + 1,
-                    index_offset + 2,
-                    index_offset + 2,
-                    index_offset + 3,
-                    index_offset,
-                ]);
-
+                self.push_glyph(vertices, indices, glyph_info, current_x, y, color);
                 current_x += self.fixed_advance;
             }
         }
@@ -877,50 +902,439 @@ impl TextRenderer {
         Ok(())
     }
 
-    pub fn update_vertex_buffer(&self, vertices: &[Vertex]) -> Result<(), vk::Result> {
-        let data_size = (vertices.len() * mem::size_of::<Vertex>()) as vk::DeviceSize;
+    /// Draws one cell's grapheme cluster with its left edge at `x` and baseline `y`. The
+    /// base character is centred across the `columns` cells it covers and combining marks
+    /// the font knows are drawn over it; joiners and variation selectors draw nothing.
+    #[allow(clippy::too_many_arguments)]
+    pub fn render_cell_to_buffer(
+        &mut self,
+        vertices: &mut Vec<Vertex>,
+        indices: &mut Vec<u32>,
+        text: &str,
+        columns: usize,
+        x: f32,
+        y: f32,
+        color: [f32; 4],
+        command_pool: vk::CommandPool,
+        graphics_queue: vk::Queue,
+        physical_device: vk::PhysicalDevice,
+        instance: &ash::Instance,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mut chars = text.chars();
+        let Some(base) = chars.next() else {
+            return Ok(());
+        };
+        let marks: Vec<char> = chars
+            .filter(|&ch| ch.width() == Some(0) && self.font.lookup_glyph_index(ch) != 0)
+            .collect();
 
-        unsafe {
-            let data_ptr = self.device.map_memory(
+        let mut texture_updated = false;
+        for &ch in std::iter::once(&base).chain(&marks) {
+            if !self.glyph_cache.contains_key(&ch) {
+                self.cache_glyph(ch)?;
+                texture_updated = true;
+            }
+        }
+
+        let base_glyph = &self.glyph_cache[&base];
+        let cell_width = columns as f32 * self.fixed_advance;
+        let base_x = x + ((cell_width - base_glyph.advance) / 2.0).max(0.0);
+        self.push_glyph(vertices, indices, base_glyph, base_x, y, color);
+
+        for ch in marks {
+            let glyph_info = &self.glyph_cache[&ch];
+            // Zero-advance marks are positioned relative to the end of the base glyph
+            let mark_x = if glyph_info.advance == 0.0 {
+                base_x + base_glyph.advance
+            } else {
+                base_x
+            };
+            self.push_glyph(vertices, indices, glyph_info, mark_x, y, color);
+        }
+
+        if texture_updated {
+            self.update_texture(command_pool, graphics_queue, physical_device, instance)?;
+        }
+
+        Ok(())
+    }
+
+    /// Appends the quad for a cached glyph with its origin at `x` and baseline `y`.
+    fn push_glyph(
+        &self,
+        vertices: &mut Vec<Vertex>,
+        indices: &mut Vec<u32>,
+        glyph_info: &GlyphInfo,
+        x: f32,
+        y: f32,
+        color: [f32; 4],
+    ) {
+        if glyph_info.width == 0 || glyph_info.height == 0 {
+            return;
+        }
+
+        let w = glyph_info.width as f32;
+        let h = glyph_info.height as f32;
+
+        // `y` is the baseline; bearing_y (ymin) is the glyph's bottom edge above it
+        let x_pos = x + glyph_info.bearing_x as f32;
+        let y_pos = y - glyph_info.bearing_y as f32 - h;
+
+        let u0 = glyph_info.texture_id as f32 / self.atlas_width as f32;
+        let v0 = glyph_info.texture_y as f32 / self.atlas_height as f32;
+        let u1 = (glyph_info.texture_id + glyph_info.width) as f32 / self.atlas_width as f32;
+        let v1 = (glyph_info.texture_y + glyph_info.height) as f32 / self.atlas_height as f32;
+
+        let index_offset = vertices.len() as u32;
+
+        vertices.extend_from_slice(&[
+            Vertex {
+                position: [x_pos, y_pos + h],
+                tex_coord: [u0, v1],
+                color,
+            },
+            Vertex {
+                position: [x_pos, y_pos],
+                tex_coord: [u0, v0],
+                color,
+            },
+            Vertex {
+                position: [x_pos + w, y_pos],
+                tex_coord: [u1, v0],
+                color,
+            },
+            Vertex {
+                position: [x_pos + w, y_pos + h],
+                tex_coord: [u1, v1],
+                color,
+            },
+        ]);
+
+        indices.extend_from_slice(&[
+            index_offset,
+            index_offset + 1,
+            index_offset + 2,
+            index_offset + 2,
+            index_offset + 3,
+            index_offset,
+        ]);
+    }
+
+    /// Appends a solid rectangle, used for cell backgrounds and decorations.
+    #[allow(clippy::too_many_arguments)]
+    pub fn render_rect_to_buffer(
+        &self,
+        vertices: &mut Vec<Vertex>,
+        indices: &mut Vec<u32>,
+        x: f32,
+        y: f32,
+        width: f32,
+        height: f32,
+        color: [f32; 4],
+    ) {
+        // Sample the middle of the solid block so linear filtering never reaches the edge
+        let u = (SOLID_BLOCK_SIZE as f32 / 2.0) / self.atlas_width as f32;
+        let v = (SOLID_BLOCK_SIZE as f32 / 2.0) / self.atlas_height as f32;
+        let index_offset = vertices.len() as u32;
+
+        vertices.extend_from_slice(&[
+            Vertex {
+                position: [x, y + height],
+                tex_coord: [u, v],
+                color,
+            },
+            Vertex {
+                position: [x, y],
+                tex_coord: [u, v],
+                color,
+            },
+            Vertex {
+                position: [x + width, y],
+                tex_coord: [u, v],
+                color,
+            },
+            Vertex {
+                position: [x + width, y + height],
+                tex_coord: [u, v],
+                color,
+            },
+        ]);
+
+        indices.extend_from_slice(&[
+            index_offset,
+            index_offset + 1,
+            index_offset + 2,
+            index_offset + 2,
+            index_offset + 3,
+            index_offset,
+        ]);
+    }
+
+    /// Draws an underline of the given style under `width` pixels starting at `x`, just
+    /// below the baseline `y`. Patterns are aligned to absolute x so they continue
+    /// seamlessly across cells.
+    #[allow(clippy::too_many_arguments)]
+    pub fn render_underline_to_buffer(
+        &self,
+        vertices: &mut Vec<Vertex>,
+        indices: &mut Vec<u32>,
+        style: UnderlineStyle,
+        x: f32,
+        y: f32,
+        width: f32,
+        color: [f32; 4],
+    ) {
+        let thickness = self.line_thickness;
+        let top = y + thickness;
+
+        match style {
+            UnderlineStyle::None => {}
+            UnderlineStyle::Single => {
+                self.render_rect_to_buffer(vertices, indices, x, top, width, thickness, color);
+            }
+            UnderlineStyle::Double => {
+                self.render_rect_to_buffer(vertices, indices, x, top, width, thickness, color);
+                let second = top + thickness * 2.0;
+                self.render_rect_to_buffer(vertices, indices, x, second, width, thickness, color);
+            }
+            UnderlineStyle::Curly => {
+                // One period of a sine wave per cell, drawn a pixel column at a time
+                let amplitude = thickness * 1.5;
+                let period = self.fixed_advance;
+                let start = x.floor() as i32;
+                for column in start..(x + width).ceil() as i32 {
+                    let phase = (column as f32 / period) * std::f32::consts::TAU;
+                    let wave_y = top + amplitude * (1.0 - phase.sin());
+                    self.render_rect_to_buffer(
+                        vertices,
+                        indices,
+                        column as f32,
+                        wave_y,
+                        1.0,
+                        thickness,
+                        color,
+                    );
+                }
+            }
+            UnderlineStyle::Dotted | UnderlineStyle::Dashed => {
+                let (on, period) = if style == UnderlineStyle::Dotted {
+                    (thickness, thickness * 2.0)
+                } else {
+                    (thickness * 3.0, thickness * 5.0)
+                };
+                let end = x + width;
+                let mut segment = (x / period).floor() * period;
+                while segment < end {
+                    let left = segment.max(x);
+                    let right = (segment + on).min(end);
+                    if right > left {
+                        self.render_rect_to_buffer(
+                            vertices,
+                            indices,
+                            left,
+                            top,
+                            right - left,
+                            thickness,
+                            color,
+                        );
+                    }
+                    segment += period;
+                }
+            }
+        }
+    }
+
+    /// Grows the vertex and index buffers so the given counts fit.
+    fn ensure_buffer_capacity(
+        &mut self,
+        vertex_count: usize,
+        index_count: usize,
+        physical_device: vk::PhysicalDevice,
+        instance: &ash::Instance,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if vertex_count <= self.vertex_capacity && index_count <= self.index_capacity {
+            return Ok(());
+        }
+
+        // The previous frame may still be reading the old buffers
+        unsafe { self.device.device_wait_idle()? };
+
+        if vertex_count > self.vertex_capacity {
+            let capacity = vertex_count.next_power_of_two();
+            let (buffer, memory) =
+                Self::create_vertex_buffer(&self.device, physical_device, instance, capacity)?;
+            unsafe {
+                self.device.destroy_buffer(self.vertex_buffer, None);
+                self.device.free_memory(self.vertex_buffer_memory, None);
+            }
+            self.vertex_buffer = buffer;
+            self.vertex_buffer_memory = memory;
+            self.vertex_capacity = capacity;
+        }
+
+        if index_count > self.index_capacity {
+            let capacity = index_count.next_power_of_two();
+            let (buffer, memory) =
+                Self::create_index_buffer(&self.device, physical_device, instance, capacity)?;
+            unsafe {
+                self.device.destroy_buffer(self.index_buffer, None);
+                self.device.free_memory(self.index_buffer_memory, None);
+            }
+            self.index_buffer = buffer;
+            self.index_buffer_memory = memory;
+            self.index_capacity = capacity;
+        }
+
+        Ok(())
+    }
+
+    /// Uploads batches into their slots of the vertex and index buffers. Only batches
+    /// flagged in `dirty` are written, unless one outgrew its slot or the number of batches
+    /// changed, in which case the slots are laid out again and everything is written.
+    pub fn upload_batches(
+        &mut self,
+        batches: &[&Batch],
+        dirty: &[bool],
+        physical_device: vk::PhysicalDevice,
+        instance: &ash::Instance,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let fits = self.batch_slots.len() == batches.len()
+            && batches.iter().zip(&self.batch_slots).all(|(batch, slot)| {
+                batch.vertices.len() <= slot.vertex_capacity
+                    && batch.indices.len() <= slot.index_capacity
+            });
+
+        if !fits {
+            // Frames still in flight read the old layout
+            unsafe { self.device.device_wait_idle()? };
+
+            // Leave room for each batch to double before another layout is needed
+            let mut first_vertex = 0;
+            let mut first_index = 0;
+            self.batch_slots = batches
+                .iter()
+                .map(|batch| {
+                    let vertex_capacity = (batch.vertices.len() * 2).max(MIN_BATCH_VERTICES);
+                    let index_capacity = (batch.indices.len() * 2).max(MIN_BATCH_VERTICES / 4 * 6);
+                    let slot = BatchSlot {
+                        first_vertex,
+                        vertex_capacity,
+                        first_index,
+                        index_capacity,
+                        index_count: 0,
+                    };
+                    first_vertex += vertex_capacity;
+                    first_index += index_capacity;
+                    slot
+                })
+                .collect();
+            self.ensure_buffer_capacity(first_vertex, first_index, physical_device, instance)?;
+        }
+
+        for (index, batch) in batches.iter().enumerate() {
+            if fits && !dirty[index] {
+                continue;
+            }
+            let slot = &mut self.batch_slots[index];
+            slot.index_count = batch.indices.len();
+            let slot = *slot;
+            Self::write_buffer(
+                &self.device,
                 self.vertex_buffer_memory,
-                0,
-                data_size,
+                slot.first_vertex * mem::size_of::<Vertex>(),
+                bytemuck::cast_slice(&batch.vertices),
+            )?;
+            Self::write_buffer(
+                &self.device,
+                self.index_buffer_memory,
+                slot.first_index * mem::size_of::<u32>(),
+                bytemuck::cast_slice(&batch.indices),
+            )?;
+        }
+
+        Ok(())
+    }
+
+    fn write_buffer(
+        device: &Device,
+        memory: vk::DeviceMemory,
+        offset: usize,
+        data: &[u8],
+    ) -> Result<(), vk::Result> {
+        if data.is_empty() {
+            return Ok(());
+        }
+
+        unsafe {
+            let data_ptr = device.map_memory(
+                memory,
+                offset as vk::DeviceSize,
+                data.len() as vk::DeviceSize,
                 vk::MemoryMapFlags::empty(),
             )?;
 
-            std::ptr::copy_nonoverlapping(
-                vertices.as_ptr() as *const u8,
-                data_ptr as *mut u8,
-                data_size as usize,
-            );
+            std::ptr::copy_nonoverlapping(data.as_ptr(), data_ptr as *mut u8, data.len());
 
-            self.device.unmap_memory(self.vertex_buffer_memory);
+            device.unmap_memory(memory);
         }
 
         Ok(())
     }
 
-    pub fn update_index_buffer(&self, indices: &[u16]) -> Result<(), vk::Result> {
-        let data_size = (indices.len() * mem::size_of::<u16>()) as vk::DeviceSize;
+    /// Records draws for the uploaded batches, in the order they were passed to
+    /// `upload_batches`.
+    pub fn draw_batches(&self, command_buffer: vk::CommandBuffer, screen_dimensions: [f32; 2]) {
+        if self.batch_slots.iter().all(|slot| slot.index_count == 0) {
+            return;
+        }
 
         unsafe {
-            let data_ptr = self.device.map_memory(
-                self.index_buffer_memory,
+            self.device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.graphics_pipeline,
+            );
+
+            self.device
+                .cmd_bind_vertex_buffers(command_buffer, 0, &[self.vertex_buffer], &[0]);
+
+            self.device.cmd_bind_index_buffer(
+                command_buffer,
+                self.index_buffer,
                 0,
-                data_size,
-                vk::MemoryMapFlags::empty(),
-            )?;
+                vk::IndexType::UINT32,
+            );
 
-            std::ptr::copy_nonoverlapping(
-                indices.as_ptr() as *const u8,
-                data_ptr as *mut u8,
-                data_size as usize,
+            self.device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline_layout,
+                0,
+                &self.descriptor_sets,
+                &[],
             );
 
-            self.device.unmap_memory(self.index_buffer_memory);
-        }
+            let push_constants = PushConstants { screen_dimensions };
+            self.device.cmd_push_constants(
+                command_buffer,
+                self.pipeline_layout,
+                vk::ShaderStageFlags::VERTEX,
+                0,
+                bytemuck::bytes_of(&push_constants),
+            );
 
-        Ok(())
+            // Batch indices start at 0, so each draw offsets them to its slot's vertices
+            for slot in self.batch_slots.iter().filter(|slot| slot.index_count > 0) {
+                self.device.cmd_draw_indexed(
+                    command_buffer,
+                    slot.index_count as u32,
+                    1,
+                    slot.first_index as u32,
+                    slot.first_vertex as i32,
+                    0,
+                );
+            }
+        }
     }
 
     pub fn cache_glyph(&mut self, ch: char) -> Result<(), Box<dyn std::error::Error>> {
@@ -929,7 +1343,8 @@ impl TextRenderer {
 
             if self.atlas_x + metrics.width as u32 > self.atlas_width {
                 self.atlas_x = 0;
-                self.atlas_y += self.font_size as u32;
+                self.atlas_y += self.atlas_row_height;
+                self.atlas_row_height = 0;
             }
 
             if self.atlas_y + metrics.height as u32 > self.atlas_height {
@@ -946,15 +1361,17 @@ impl TextRenderer {
 
             let glyph_info = GlyphInfo {
                 texture_id: self.atlas_x,
+                texture_y: self.atlas_y,
                 width: metrics.width as u32,
                 height: metrics.height as u32,
                 bearing_x: metrics.xmin,
                 bearing_y: metrics.ymin,
-                advance: self.fixed_advance,
+                advance: metrics.advance_width,
             };
 
             self.glyph_cache.insert(ch, glyph_info);
             self.atlas_x += metrics.width as u32;
+            self.atlas_row_height = self.atlas_row_height.max(metrics.height as u32);
         }
         Ok(())
     }
@@ -48,7 +48,7 @@ impl VulkanContext {
             ..Default::default()
         };
 
-        let layer_names = vec![CString::new("VK_LAYER_KHRONOS_validation")?];
+        let layer_names = [CString::new("VK_LAYER_KHRONOS_validation")?];
         let layers_names_raw: Vec<*const i8> = layer_names
             .iter()
             .map(|raw_name| raw_name.as_ptr())
@@ -349,8 +349,10 @@ impl VulkanContext {
         })
     }
 
+    /// Renders one frame: clears to `clear_color`, then lets `record_commands` add draws.
     pub fn draw_frame<F>(
         &mut self,
+        clear_color: [f32; 4],
         mut record_commands: F,
     ) -> Result<(), Box<dyn std::error::Error>>
     where
@@ -391,6 +393,7 @@ impl VulkanContext {
             self.record_command_buffer(
                 self.command_buffers[self.current_frame],
                 image_index as usize,
+                clear_color,
                 &mut record_commands,
             )?;
 
@@ -441,6 +444,7 @@ impl VulkanContext {
         &self,
         command_buffer: vk::CommandBuffer,
         image_index: usize,
+        clear_color: [f32; 4],
         record_commands: &mut F,
     ) -> Result<(), Box<dyn std::error::Error>>
     where
@@ -454,7 +458,7 @@ impl VulkanContext {
 
             let clear_values = [vk::ClearValue {
                 color: vk::ClearColorValue {
-                    float32: [0.2, 0.3, 0.8, 1.0], // Blue color instead of black
+                    float32: clear_color,
                 },
             }];
 